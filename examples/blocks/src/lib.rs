@@ -1,7 +1,12 @@
 use emg::prelude::*;
 
+/// A section of fortress wall battlement, with a red wall and a black top
 #[emg]
-fn build_the_model(_a: i32) -> Result<GLTF, ErrorCode> {
+fn build_the_model(
+  /// Not used yet
+  #[emg(default = 1, range = 0..=10)]
+  _a: i32,
+) -> Result<GLTF, ErrorCode> {
   let mut gltf = GLTF::new("A name for a scene");
   
   let node = gltf.nodes.len() as u32;
@@ -31,3 +36,66 @@ fn build_the_model(_a: i32) -> Result<GLTF, ErrorCode> {
   
   Ok(gltf)
 }
+
+/// Surface finish for a block
+#[derive(EnumParameter)]
+enum Finish {
+  Matte,
+  Glossy,
+  Metal,
+}
+
+/// A single block, in any color
+#[emg]
+fn block(
+  /// Color of the block
+  #[emg(default = 0x808080)]
+  color: Color3,
+  
+  /// Surface finish of the block
+  #[emg(default = Finish::Matte)]
+  finish: Finish,
+  
+  /// Leave the top of the block open
+  #[emg(default = false)]
+  open_top: bool,
+  
+  /// Name of the block's node in the scene
+  #[emg(default = "Block")]
+  name: &str,
+) -> Result<GLTF, Error> {
+  if name.is_empty() {
+    return Err(Error::new(ErrorCode::Generation, "block name cannot be empty"));
+  }
+  
+  emg::debug!("Building block `{}`", name);
+  
+  let mut gltf = GLTF::new("Block");
+  
+  let node = gltf.nodes.len() as u32;
+  gltf.new_root_node(0, name);
+  
+  let mesh = gltf.meshes.len();
+  gltf.new_mesh(node, name);
+  
+  let (metalness, roughness) = match finish {
+    Finish::Matte => (0.0, 0.9),
+    Finish::Glossy => (0.0, 0.2),
+    Finish::Metal => (1.0, 0.3),
+  };
+  
+  let material = gltf.materials.len() as u32;
+  gltf.new_material("Block").m(metalness).rh(roughness)
+    .rgba(color.r, color.g, color.b, 1.0);
+  
+  let mut block = Geometry::cube();
+  if open_top {
+    block.select_triangles(V3::new(-10.0, -10.0, 0.5),
+      V3::new(10.0, 10.0, 10.0));
+    block.delete_triangles();
+  }
+  let submesh = block.pack(&mut gltf);
+  gltf.meshes[mesh].copy_primitive(submesh).material(material);
+  
+  Ok(gltf)
+}
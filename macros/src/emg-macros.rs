@@ -1,79 +1,459 @@
-fn argument_type_error(node: impl syn::spanned::Spanned,
+use syn::spanned::Spanned;
+
+fn argument_type_error(node: impl Spanned,
 ) -> proc_macro::TokenStream {
   quote::quote_spanned! {
     node.span() => compile_error!("emg arguments must be `i32`, `i64`, \
-      `f32`, or `f64`");
+      `f32`, `f64`, `bool`, `Color3`, `&str`, `String`, or an enum deriving \
+      `EnumParameter`");
   }.into()
 }
 
-fn return_type_error(node: impl syn::spanned::Spanned,
+fn return_type_error(node: impl Spanned,
 ) -> proc_macro::TokenStream {
   quote::quote_spanned! {
     node.span() => compile_error!("emg return type must be `Result<GLTF, \
-      i32>`");
+      ErrorCode>` or `Result<GLTF, Error>`");
   }.into()
 }
 
+/// Collects the text of any doc comments in a list of attributes. Rustdoc
+/// ignores the space after `///`, so that is removed here too
+fn doc_comment(attrs: &[syn::Attribute]) -> String {
+  let mut lines = Vec::new();
+  
+  for attr in attrs {
+    if !attr.path().is_ident("doc") {
+      continue;
+    }
+    
+    if let syn::Meta::NameValue(name_value) = &attr.meta {
+      if let syn::Expr::Lit(syn::ExprLit {
+        lit: syn::Lit::Str(string), ..
+      }) = &name_value.value {
+        let line = string.value();
+        lines.push(String::from(line.strip_prefix(' ').unwrap_or(&line)));
+      }
+    }
+  }
+  
+  String::from(lines.join("\n").trim())
+}
+
+/// How a model generator parameter is passed through WebAssembly
+enum ParameterKind {
+  /// `i32`, `i64`, `f32`, or `f64`, passed as-is
+  Number,
+  
+  /// Passed as an `i32` of 0 or 1
+  Bool,
+  
+  /// `Color3`, passed as an `i32` of 0xRRGGBB
+  Color,
+  
+  /// Enum deriving `EnumParameter`, passed as an `i32` variant index. Holds the
+  /// enum's name
+  Enum(String),
+  
+  /// `&str` or `String`, passed as an `i32` pointer to memory from
+  /// `emg_alloc()` and an `i32` length
+  String { owned: bool },
+}
+
+fn parameter_kind(type_: &syn::Type) -> Option<ParameterKind> {
+  let str_type: syn::Type = syn::parse_str("str").unwrap();
+  
+  let path = match type_ {
+    syn::Type::Path(type_path) if type_path.qself.is_none() => &type_path.path,
+    syn::Type::Reference(reference) if reference.lifetime.is_none() &&
+      reference.mutability.is_none() && *reference.elem == str_type => {
+      return Some(ParameterKind::String { owned: false });
+    },
+    _ => return None,
+  };
+  
+  let last = path.segments.last()?;
+  if !last.arguments.is_empty() {
+    return None;
+  }
+  
+  let name = last.ident.to_string();
+  let primitive = path.segments.len() == 1;
+  // Any other type from the standard library cannot be an enum deriving
+  // EnumParameter, so is reported as unsupported here instead of failing
+  // later with a confusing trait bound error
+  let standard = ["std", "core", "alloc"].iter().any(|crate_|
+    path.segments[0].ident == crate_);
+  
+  Some(match name.as_str() {
+    "i32" | "i64" | "f32" | "f64" if primitive => ParameterKind::Number,
+    "bool" if primitive => ParameterKind::Bool,
+    "Color3" => ParameterKind::Color,
+    "String" => ParameterKind::String { owned: true },
+    "i8" | "i16" | "i128" | "isize" | "u8" | "u16" | "u32" | "u64" | "u128" |
+    "usize" | "char" | "str" if primitive => return None,
+    "PathBuf" | "OsString" | "CString" | "Duration" => return None,
+    _ if standard => return None,
+    _ => ParameterKind::Enum(name),
+  })
+}
+
+/// Settings from `#[emg(...)]` attributes on a model generator parameter
+struct ParameterOptions {
+  default: Option<syn::Expr>,
+  min: Option<syn::Expr>,
+  max: Option<syn::Expr>,
+}
+
+fn parameter_options(attrs: &[syn::Attribute]) -> syn::Result<ParameterOptions>
+{
+  let mut options = ParameterOptions { default: None, min: None, max: None };
+  
+  for attr in attrs {
+    if !attr.path().is_ident("emg") {
+      continue;
+    }
+    
+    attr.parse_nested_meta(|meta| {
+      if meta.path.is_ident("default") {
+        options.default = Some(meta.value()?.parse()?);
+        return Ok(());
+      }
+      
+      if meta.path.is_ident("range") {
+        let range = match meta.value()?.parse()? {
+          syn::Expr::Range(range) => range,
+          other => return Err(syn::Error::new_spanned(other, "emg ranges \
+            must be written as `min..=max`, `min..`, or `..=max`")),
+        };
+        
+        if let (syn::RangeLimits::HalfOpen(_), Some(_)) = (&range.limits,
+          &range.end) {
+          return Err(syn::Error::new_spanned(range, "emg ranges must \
+            include their maximum, as in `min..=max`"));
+        }
+        
+        options.min = range.start.map(|start| *start);
+        options.max = range.end.map(|end| *end);
+        return Ok(());
+      }
+      
+      Err(meta.error("emg parameter options are `default` and `range`"))
+    })?;
+  }
+  
+  Ok(options)
+}
+
+/// Converts a numeric literal, which may be negative, into a JSON number
+fn json_number(expr: &syn::Expr) -> syn::Result<String> {
+  let (negative, literal) = match expr {
+    syn::Expr::Unary(syn::ExprUnary { op: syn::UnOp::Neg(_), expr, .. }) =>
+      (true, &**expr),
+    _ => (false, expr),
+  };
+  
+  let digits = match literal {
+    syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(int), .. }) =>
+      int.base10_parse::<i128>()?.to_string(),
+    syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Float(float), .. }) =>
+      float.base10_parse::<f64>()?.to_string(),
+    _ => return Err(syn::Error::new_spanned(expr, "emg parameter defaults \
+      and ranges must be numbers")),
+  };
+  
+  Ok(if negative { format!("-{}", digits) } else { digits })
+}
+
+/// Converts a parameter default into JSON. Colors are written as `#rrggbb`
+/// and enum variants by name, the same as they are given to the CLI
+fn json_default(kind: &ParameterKind, default: &syn::Expr) ->
+syn::Result<String> {
+  match (kind, default) {
+    (ParameterKind::Number, _) => json_number(default),
+    (ParameterKind::Bool, syn::Expr::Lit(syn::ExprLit {
+      lit: syn::Lit::Bool(bool_), ..
+    })) => Ok(bool_.value.to_string()),
+    (ParameterKind::Color, syn::Expr::Lit(syn::ExprLit {
+      lit: syn::Lit::Int(int), ..
+    })) => match int.base10_parse::<u32>()? {
+      hex if hex <= 0xffffff => Ok(json_string(&format!("#{:06x}", hex))),
+      _ => Err(syn::Error::new_spanned(int, "colors must be between 0x000000 \
+        and 0xffffff")),
+    },
+    (ParameterKind::String { .. }, syn::Expr::Lit(syn::ExprLit {
+      lit: syn::Lit::Str(string), ..
+    })) => Ok(json_string(&string.value())),
+    (ParameterKind::Enum(_), syn::Expr::Path(path)) => match path.path.segments
+      .last() {
+      Some(variant) => Ok(json_string(&variant.ident.to_string())),
+      None => Err(syn::Error::new_spanned(path, "expected an enum variant")),
+    },
+    (ParameterKind::Bool, _) => Err(syn::Error::new_spanned(default,
+      "bool defaults must be `true` or `false`")),
+    (ParameterKind::Color, _) => Err(syn::Error::new_spanned(default,
+      "color defaults must be written as 0xRRGGBB")),
+    (ParameterKind::Enum(_), _) => Err(syn::Error::new_spanned(default,
+      "enum defaults must be written as a variant, such as `Finish::Matte`")),
+    (ParameterKind::String { .. }, _) => Err(syn::Error::new_spanned(default,
+      "string defaults must be string literals")),
+  }
+}
+
+/// Quotes and escapes a string for use in the JSON metadata
+fn json_string(string: &str) -> String {
+  let mut result = String::from("\"");
+  
+  for c in string.chars() {
+    match c {
+      '"' => result.push_str("\\\""),
+      '\\' => result.push_str("\\\\"),
+      '\n' => result.push_str("\\n"),
+      '\t' => result.push_str("\\t"),
+      c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}",
+        c as u32)),
+      c => result.push(c),
+    }
+  }
+  
+  result.push('"');
+  result
+}
+
 #[proc_macro_attribute]
 pub fn emg(
   _args: proc_macro::TokenStream,
   input: proc_macro::TokenStream,
 ) -> proc_macro::TokenStream {
-  let input_fn = syn::parse_macro_input!(input as syn::ItemFn);
-  let signature = input_fn.sig.clone();
-  let base_name = signature.ident.clone();
-  let args = signature.inputs.clone();
+  let mut input_fn = syn::parse_macro_input!(input as syn::ItemFn);
+  let base_name = input_fn.sig.ident.clone();
   
   let full_name = syn::Ident::new(format!("gen_{base_name}").as_str(),
     base_name.clone().span());
   
-  let mut arg_names: syn::punctuated::Punctuated<syn::Pat, syn::token::Comma> =
-    syn::punctuated::Punctuated::new();
+  // The exported function takes WebAssembly types, which are converted to the
+  // generator's parameter types before calling it
+  let mut wrapper_args = Vec::new();
+  let mut converted_args = Vec::new();
   
-  let expected_argument_types: Vec<syn::Type> = vec![
-    syn::parse_str("i32").unwrap(),
-    syn::parse_str("i64").unwrap(),
-    syn::parse_str("f32").unwrap(),
-    syn::parse_str("f64").unwrap(),
-  ];
+  let mut parameter_metadata = Vec::new();
+  let mut type_checks = Vec::new();
   
-  for pair in args.clone().into_pairs() {
-    match pair.into_tuple().0 {
+  for arg in input_fn.sig.inputs.iter_mut() {
+    match arg {
       syn::FnArg::Receiver(receiver) => return argument_type_error(receiver),
       syn::FnArg::Typed(pat_type) => {
-        if !expected_argument_types.contains(&pat_type.ty) {
-          return argument_type_error(pat_type.ty);
+        let kind = match parameter_kind(&pat_type.ty) {
+          Some(k) => k,
+          None => return argument_type_error(&pat_type.ty),
+        };
+        
+        // Doc comments are not allowed on function parameters, and #[emg]
+        // attributes only have meaning here, so both must be removed after
+        // reading them
+        let doc = doc_comment(&pat_type.attrs);
+        let options = match parameter_options(&pat_type.attrs) {
+          Ok(o) => o,
+          Err(e) => return e.to_compile_error().into(),
+        };
+        pat_type.attrs.retain(|attr| !attr.path().is_ident("doc") &&
+          !attr.path().is_ident("emg"));
+        
+        let name = match &*pat_type.pat {
+          syn::Pat::Ident(pat_ident) => pat_ident.ident.to_string(),
+          _ => String::new(),
+        };
+        
+        let type_ = &pat_type.ty;
+        let pat = &pat_type.pat;
+        
+        // Number types print as WebAssembly type names
+        let type_name = match &kind {
+          ParameterKind::Number => quote::quote!(#type_).to_string(),
+          ParameterKind::Bool => String::from("bool"),
+          ParameterKind::Color => String::from("color"),
+          ParameterKind::Enum(_) => String::from("enum"),
+          ParameterKind::String { .. } => String::from("string"),
+        };
+        
+        let mut metadata = format!("{{\"name\":{},\"type\":{},\"doc\":{}",
+          json_string(&name), json_string(&type_name), json_string(&doc));
+        
+        if let ParameterKind::Enum(enum_name) = &kind {
+          metadata += &format!(",\"enum\":{}", json_string(enum_name));
+        }
+        
+        if let Some(default) = &options.default {
+          match json_default(&kind, default) {
+            Ok(json) => metadata += &format!(",\"default\":{}", json),
+            Err(e) => return e.to_compile_error().into(),
+          }
+          
+          // Colors and strings are checked by json_default(), since colors
+          // are written as integers and a String cannot be const
+          let checked = matches!(kind, ParameterKind::Color |
+            ParameterKind::String { .. });
+          if !checked {
+            type_checks.push(quote::quote_spanned! {
+              default.span() => const _: #type_ = #default;
+            });
+          }
+        }
+        
+        for (key, value) in [("min", &options.min), ("max", &options.max)] {
+          if let Some(value) = value {
+            if !matches!(kind, ParameterKind::Number) {
+              return syn::Error::new_spanned(value, "emg ranges are only \
+                supported on number parameters").to_compile_error().into();
+            }
+            
+            match json_number(value) {
+              Ok(number) => metadata += &format!(",\"{}\":{}", key, number),
+              Err(e) => return e.to_compile_error().into(),
+            }
+            
+            // Lets the compiler check that the value fits the parameter type
+            type_checks.push(quote::quote_spanned! {
+              value.span() => const _: #type_ = #value;
+            });
+          }
+        }
+        
+        // A default outside the range would only be rejected when a model is
+        // generated without that parameter
+        if let Some(default) = &options.default {
+          if let Some(min) = &options.min {
+            type_checks.push(quote::quote_spanned! {
+              default.span() => const _: () = {
+                let (default, min): (#type_, #type_) = (#default, #min);
+                assert!(default >= min, "emg parameter default is below the \
+                  minimum of its range");
+              };
+            });
+          }
+          if let Some(max) = &options.max {
+            type_checks.push(quote::quote_spanned! {
+              default.span() => const _: () = {
+                let (default, max): (#type_, #type_) = (#default, #max);
+                assert!(default <= max, "emg parameter default is above the \
+                  maximum of its range");
+              };
+            });
+          }
+        }
+        
+        metadata.push('}');
+        parameter_metadata.push(metadata);
+        
+        match kind {
+          ParameterKind::Number => {
+            wrapper_args.push(quote::quote!(#pat: #type_));
+            converted_args.push(quote::quote!(#pat));
+          },
+          ParameterKind::Bool => {
+            wrapper_args.push(quote::quote!(#pat: i32));
+            converted_args.push(quote::quote!(#pat != 0));
+          },
+          ParameterKind::Color => {
+            wrapper_args.push(quote::quote!(#pat: i32));
+            converted_args.push(quote::quote!(emg::Color3::from_hex(#pat)));
+          },
+          ParameterKind::Enum(_) => {
+            wrapper_args.push(quote::quote!(#pat: i32));
+            converted_args.push(quote::quote! {
+              match <#type_ as emg::EnumParameter>::from_index(#pat) {
+                Some(variant) => variant,
+                None => return ErrorCode::ParameterOutOfRange as i32,
+              }
+            });
+          },
+          ParameterKind::String { owned } => {
+            let ident = match &**pat {
+              syn::Pat::Ident(pat_ident) => &pat_ident.ident,
+              _ => return syn::Error::new_spanned(pat, "string parameters \
+                must be plain names").to_compile_error().into(),
+            };
+            let pointer = quote::format_ident!("{}_pointer", ident);
+            let length = quote::format_ident!("{}_length", ident);
+            
+            wrapper_args.push(quote::quote!(#pointer: i32, #length: i32));
+            
+            // The memory was allocated by emg::emg_alloc() with a capacity of
+            // exactly the length, and the host does not use it again
+            let string = quote::quote! {
+              match String::from_utf8(unsafe { Vec::from_raw_parts(
+                #pointer as *mut u8, #length as usize, #length as usize) }) {
+                Ok(string) => string,
+                Err(_) => return ErrorCode::ParameterType as i32,
+              }
+            };
+            
+            // For &str, the String is a temporary that lives until the model
+            // generator returns
+            converted_args.push(if owned { string } else {
+              quote::quote!(&#string)
+            });
+          },
         }
-        arg_names.push(*pat_type.pat);
       },
     }
   }
   
-  let expected_return_type: syn::Type = syn::parse_str(
-    "Result<GLTF, ErrorCode>").unwrap();
+  let signature = input_fn.sig.clone();
+  
+  let expected_return_types: Vec<syn::Type> = vec![
+    syn::parse_str("Result<GLTF, ErrorCode>").unwrap(),
+    syn::parse_str("Result<GLTF, Error>").unwrap(),
+    syn::parse_str("Result<GLTF, emg::Error>").unwrap(),
+  ];
   
   match signature.output.clone() {
     syn::ReturnType::Type(_, box_type) => {
-      if *box_type != expected_return_type {
+      if !expected_return_types.contains(&box_type) {
         return return_type_error(*box_type);
       }
     },
     _ => return return_type_error(signature.clone()),
   }
   
+  // Metadata is one line of JSON per generator. The linker concatenates custom
+  // sections with the same name, so all generators share the `emg` section
+  let metadata = format!("{{\"name\":{},\"doc\":{},\"parameters\":[{}]}}\n",
+    json_string(&base_name.to_string()),
+    json_string(&doc_comment(&input_fn.attrs)), parameter_metadata.join(","));
+  let metadata_length = metadata.len();
+  let metadata_bytes = syn::LitByteStr::new(metadata.as_bytes(),
+    base_name.span());
+  let metadata_name = syn::Ident::new(format!("EMG_METADATA_{}",
+    base_name.to_string().to_uppercase()).as_str(), base_name.span());
+  
   proc_macro::TokenStream::from(quote::quote! {
     #input_fn
     
+    #(#type_checks)*
+    
+    // Custom sections are a WebAssembly feature, so the metadata is only
+    // placed in one when building for WebAssembly
+    #[cfg_attr(target_family = "wasm", link_section = "emg")]
+    #[used]
+    static #metadata_name: [u8; #metadata_length] = *#metadata_bytes;
+    
     #[automatically_derived]
     #[no_mangle]
-    pub extern "C" fn #full_name(#args) -> i32 {
+    pub extern "C" fn #full_name(#(#wrapper_args),*) -> i32 {
       match emg::MUTEX_TEST.try_lock() {
         Err(_) => return ErrorCode::Mutex as i32,
         Ok(mut guard) => {
           *guard = Vec::new();
+          emg::clear_error();
           
-          let gltf = match #base_name(#arg_names) {
-            Err(code) => return code as i32,
+          let gltf = match #base_name(#(#converted_args),*) {
+            Err(error) => {
+              // Both ErrorCode and emg::Error convert to emg::Error
+              let error: emg::Error = error.into();
+              emg::write_error(&mut guard, &error);
+              return error.code as i32;
+            },
             Ok(gltf) => gltf,
           };
           
@@ -85,3 +465,64 @@ pub fn emg(
     }
   })
 }
+
+#[proc_macro_derive(EnumParameter)]
+pub fn derive_enum_parameter(input: proc_macro::TokenStream) ->
+proc_macro::TokenStream {
+  let input_enum = syn::parse_macro_input!(input as syn::DeriveInput);
+  let name = &input_enum.ident;
+  
+  let data = match &input_enum.data {
+    syn::Data::Enum(data) => data,
+    _ => return quote::quote_spanned! {
+      name.span() => compile_error!("EnumParameter can only be derived for \
+        enums");
+    }.into(),
+  };
+  
+  let mut variants = Vec::new();
+  for variant in &data.variants {
+    if !matches!(variant.fields, syn::Fields::Unit) {
+      return quote::quote_spanned! {
+        variant.ident.span() => compile_error!("EnumParameter variants cannot \
+          have fields");
+      }.into();
+    }
+    
+    variants.push(&variant.ident);
+  }
+  
+  let indices = 0..variants.len() as i32;
+  let variant_names: Vec<String> = variants.iter()
+    .map(|variant| variant.to_string()).collect();
+  
+  // Enums get their own line in the `emg` section, which generators refer to
+  // by enum name
+  let variants_json: Vec<String> = variant_names.iter()
+    .map(|variant| json_string(variant)).collect();
+  let metadata = format!("{{\"enum\":{},\"doc\":{},\"variants\":[{}]}}\n",
+    json_string(&name.to_string()),
+    json_string(&doc_comment(&input_enum.attrs)), variants_json.join(","));
+  let metadata_length = metadata.len();
+  let metadata_bytes = syn::LitByteStr::new(metadata.as_bytes(), name.span());
+  let metadata_name = syn::Ident::new(format!("EMG_ENUM_METADATA_{}",
+    name.to_string().to_uppercase()).as_str(), name.span());
+  
+  proc_macro::TokenStream::from(quote::quote! {
+    #[cfg_attr(target_family = "wasm", link_section = "emg")]
+    #[used]
+    static #metadata_name: [u8; #metadata_length] = *#metadata_bytes;
+    
+    #[automatically_derived]
+    impl emg::EnumParameter for #name {
+      const VARIANTS: &'static [&'static str] = &[#(#variant_names),*];
+      
+      fn from_index(index: i32) -> Option<Self> {
+        match index {
+          #(#indices => Some(Self::#variants),)*
+          _ => None,
+        }
+      }
+    }
+  })
+}
@@ -1,13 +1,59 @@
-use std::path::PathBuf;
-use std::fmt::Write as _;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::io::Write as _;
 
 use clap::Parser;
 use base64::Engine;
 
-fn fail<S: AsRef<str>>(code: emg::ErrorCode, message: S) -> ! {
-  eprintln!("Error: {}", message.as_ref());
-  std::process::exit(code as i32);
+use emg::host::{CacheOptions, EMGModule, Format, HostError, Limits,
+  LogMessage, GLBMetadata, MAX_CACHE_SIZE, evict_cache, format_output,
+  touch_cache_entry, write_atomically};
+
+/// Why a subcommand stopped. Only main() turns these into an exit code and
+/// message
+#[derive(Debug)]
+enum Failure {
+  Host(HostError),
+  
+  /// Arguments that clap accepted but cannot be used together
+  Usage(String),
+  
+  /// A build file could not be used
+  BuildFile(String),
+  
+  /// Some models in a batch or build failed. Each was reported as it failed,
+  /// and the first failure's code is the exit code
+  Models { failed: usize, total: usize, first: HostError },
+}
+
+impl Failure {
+  fn code(&self) -> i32 {
+    match self {
+      Self::Host(failure) => failure.code(),
+      // Same exit code clap uses for its own usage errors
+      Self::Usage(_) => 2,
+      Self::BuildFile(_) => emg::ErrorCode::BuildFile as i32,
+      Self::Models { first, .. } => first.code(),
+    }
+  }
+}
+
+impl std::fmt::Display for Failure {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Host(failure) => write!(f, "{}", failure),
+      Self::Usage(message) => write!(f, "{}", message),
+      Self::BuildFile(message) => write!(f, "{}", message),
+      Self::Models { failed, total, .. } => write!(f, "{} of {} models failed",
+        failed, total),
+    }
+  }
+}
+
+impl From<HostError> for Failure {
+  fn from(failure: HostError) -> Self {
+    Self::Host(failure)
+  }
 }
 
 /// emg by Den Antares
@@ -25,26 +71,25 @@ enum Subcommands {
   /// Generate a GLTF model using an emg .wasm file
   Gen(ArgsForGen),
   
-  /// Not yet implemented - Will run a web server for viewing an emg .wasm
-  /// file
+  /// Generate a model for every combination of a set of parameter values
+  Batch(ArgsForBatch),
+  
+  /// Run a local web server for previewing the models in an emg .wasm file.
+  /// The preview page loads model-viewer from ajax.googleapis.com, so
+  /// displaying models requires network access
   Serve(ArgsForServe),
   
+  /// Generate every model listed in a build file
+  Build(ArgsForBuild),
+  
   /// Report metadata from an emg .wasm file
   Inspect(ArgsForInspect),
-}
-
-#[derive(clap::ValueEnum, Clone, Default, Debug)]
-enum Format {
-    /// GLTF binary format (.glb)
-    #[default]
-    GLB,
-    
-    /// GLTF text format (.gltf)
-    GLTF,
-    
-    /// Pretty-printed GLTF text format (for debugging, may omit buffers
-    /// required for rendering)
-    Pretty,
+  
+  /// Compile an emg .wasm file ahead of time into a .cwasm file, which the
+  /// other subcommands load without compiling. Unlike .wasm files, .cwasm
+  /// files contain machine code that is not sandboxed, so only use them from
+  /// trusted sources
+  Precompile(ArgsForPrecompile),
 }
 
 #[derive(clap::Args, Debug)]
@@ -56,448 +101,1258 @@ struct ArgsForGen {
   generator: String,
   
   /// Parameters to pass to model generator
+  #[arg(allow_negative_numbers = true)]
   parameters: Vec<String>,
   
-  /// Output format
-  #[clap(short, long, default_value_t, value_enum)]
-  format: Format,
+  /// Parameter to pass to model generator by name, as `name=value`. May be
+  /// repeated. When any are given, parameters that are not given get their
+  /// default value, or zero if they have no default
+  #[clap(long = "param", value_name = "NAME=VALUE")]
+  named_parameters: Vec<String>,
+  
+  /// Output format. If not given, it is inferred from the output file's
+  /// extension, or is glb
+  #[clap(short, long, value_enum)]
+  format: Option<OutputFormat>,
+  
+  /// Write the model to a file instead of stdout. The file is replaced
+  /// atomically, so readers never see a partly written model
+  #[clap(short, long)]
+  output: Option<PathBuf>,
+  
+  /// Same as `--format gltf-separate`
+  #[clap(long, default_value_t = false, conflicts_with = "format")]
+  external_buffers: bool,
+  
+  /// Describe the model generator and its parameters instead of running it
+  #[clap(long, default_value_t = false)]
+  help_generator: bool,
   
   /// Print additional debug info to stderr (stdout is reserved for GLTF output)
   #[clap(short, long, default_value_t = false)]
   verbose: bool,
+  
+  #[command(flatten)]
+  limits: LimitArgs,
+  
+  /// Print memory use, output size, and generation time to stderr
+  #[clap(long, default_value_t = false)]
+  stats: bool,
+  
+  #[command(flatten)]
+  cache: CacheArgs,
+  
+  /// Keep running and regenerate the model into the --output file whenever
+  /// the .wasm file changes. If regeneration fails, the last good model is
+  /// kept
+  #[clap(short, long, default_value_t = false, requires = "output")]
+  watch: bool,
 }
 
 #[derive(clap::Args, Debug)]
-struct ArgsForServe {
+struct ArgsForBatch {
   /// Path to .wasm file
   wasm: PathBuf,
+  
+  /// Name of model generator within .wasm file to use
+  generator: String,
+  
+  /// Parameter to vary, as `name=start..end:step` (end is included, and step
+  /// defaults to 1) or `name=value,value,...`. May be repeated, and a model is
+  /// generated for every combination
+  #[clap(long, value_name = "NAME=VALUES")]
+  sweep: Vec<String>,
+  
+  /// Parameter to pass to every model generator run, as `name=value`. May be
+  /// repeated. Parameters that are neither swept nor given get their default
+  /// value, or zero if they have no default
+  #[clap(long = "param", value_name = "NAME=VALUE")]
+  named_parameters: Vec<String>,
+  
+  /// Directory to write models and manifest.json to. Created if missing
+  #[clap(long)]
+  out_dir: PathBuf,
+  
+  /// File name for each model, without extension. `{generator}`, `{index}`,
+  /// and `{name}` for any named parameter are replaced with their values.
+  /// Defaults to the generator name followed by each swept parameter
+  #[clap(long, value_name = "TEMPLATE")]
+  name: Option<String>,
+  
+  /// Output format
+  #[clap(short, long, value_enum, default_value_t)]
+  format: OutputFormat,
+  
+  /// Print additional debug info to stderr
+  #[clap(short, long, default_value_t = false)]
+  verbose: bool,
+  
+  #[command(flatten)]
+  limits: LimitArgs,
+  
+  #[command(flatten)]
+  cache: CacheArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct ArgsForBuild {
+  /// Path to build file. Paths in it are relative to its directory
+  #[clap(default_value = "emg.toml")]
+  build_file: PathBuf,
+  
+  /// Number of models to generate at once. Defaults to the number of CPUs
+  #[clap(short, long)]
+  jobs: Option<usize>,
+  
+  /// Print additional debug info to stderr
+  #[clap(short, long, default_value_t = false)]
+  verbose: bool,
+  
+  #[command(flatten)]
+  limits: LimitArgs,
+  
+  #[command(flatten)]
+  cache: CacheArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct ArgsForServe {
+  /// Path to .wasm file. It is reloaded for every model request, so rebuilding
+  /// it does not require restarting the server
+  wasm: PathBuf,
+  
+  /// Address to listen on
+  #[clap(long, default_value = "127.0.0.1")]
+  host: String,
+  
+  /// Port to listen on
+  #[clap(short, long, default_value_t = 8080)]
+  port: u16,
+  
+  /// Print each request and additional debug info to stderr
+  #[clap(short, long, default_value_t = false)]
+  verbose: bool,
+  
+  #[command(flatten)]
+  limits: LimitArgs,
+  
+  #[command(flatten)]
+  cache: CacheArgs,
+}
+
+/// Command line form of emg::host::Format
+#[derive(clap::ValueEnum, Clone, Default, Debug)]
+enum OutputFormat {
+  /// GLTF binary format (.glb)
+  #[default]
+  Glb,
+  
+  /// GLTF text format (.gltf)
+  Gltf,
+  
+  /// GLTF text format (.gltf), with the BIN chunk in a .bin file next to it
+  /// instead of embedded as base64. Only for output to a file
+  GltfSeparate,
+  
+  /// Pretty-printed GLTF text format (for debugging, may omit buffers
+  /// required for rendering)
+  Pretty,
+}
+
+impl From<&OutputFormat> for Format {
+  fn from(format: &OutputFormat) -> Self {
+    match format {
+      OutputFormat::Glb => Self::GLB,
+      OutputFormat::Gltf => Self::GLTF,
+      OutputFormat::GltfSeparate => Self::GLTFSeparate,
+      OutputFormat::Pretty => Self::Pretty,
+    }
+  }
+}
+
+impl From<&Format> for OutputFormat {
+  fn from(format: &Format) -> Self {
+    match format {
+      Format::GLB => Self::Glb,
+      Format::GLTF => Self::Gltf,
+      Format::GLTFSeparate => Self::GltfSeparate,
+      Format::Pretty => Self::Pretty,
+    }
+  }
+}
+
+/// Command line form of emg::host::Limits
+#[derive(clap::Args, Debug)]
+struct LimitArgs {
+  /// Stop the model generator if it runs longer than this
+  #[clap(long, value_name = "MS")]
+  timeout: Option<u64>,
+  
+  /// Stop the model generator if it runs out of fuel. WebAssembly
+  /// instructions consume about one unit of fuel each
+  #[clap(long)]
+  fuel: Option<u64>,
+  
+  /// Do not let WebAssembly memory grow past this many bytes
+  #[clap(long, value_name = "BYTES")]
+  max_memory: Option<usize>,
+}
+
+impl From<&LimitArgs> for Limits {
+  fn from(args: &LimitArgs) -> Self {
+    Self { timeout: args.timeout, fuel: args.fuel,
+      max_memory: args.max_memory }
+  }
+}
+
+/// Command line form of emg::host::CacheOptions
+#[derive(clap::Args, Debug)]
+struct CacheArgs {
+  /// Always compile the .wasm file and run the model generator, and do not
+  /// update the cache
+  #[clap(long, default_value_t = false)]
+  no_cache: bool,
+  
+  /// Cache directory. Defaults to emg/ in the user's cache directory. If
+  /// the user has no cache directory, nothing is cached. Compiled modules are
+  /// run without being checked, so this must not be writable by other users.
+  /// Least recently used modules and models are each deleted once they take
+  /// up 512 MiB
+  #[clap(long, value_name = "DIR", env = "EMG_CACHE_DIR")]
+  cache_dir: Option<PathBuf>,
+}
+
+impl From<&CacheArgs> for CacheOptions {
+  fn from(args: &CacheArgs) -> Self {
+    Self { no_cache: args.no_cache, cache_dir: args.cache_dir.clone() }
+  }
+}
+
+#[derive(clap::ValueEnum, Clone, Default, Debug)]
+enum InspectFormat {
+  /// Human-readable summary on stderr
+  #[default]
+  Text,
+  
+  /// JSON document on stdout, for use by scripts and editor plugins
+  Json,
 }
 
 #[derive(clap::Args, Debug)]
 struct ArgsForInspect {
   /// Path to .wasm file
   wasm: PathBuf,
+  
+  /// Output format
+  #[clap(short, long, default_value_t, value_enum)]
+  format: InspectFormat,
+  
+  #[command(flatten)]
+  cache: CacheArgs,
 }
 
-// .engine and .module aren't accessed, I just keep them here to remind myself of
-// the wasmtime variables
-#[allow(dead_code)]
-struct EMGModule {
-  engine: wasmtime::Engine,
-  module: wasmtime::Module,
-  store: wasmtime::Store<()>,
-  instance: wasmtime::Instance,
+#[derive(clap::Args, Debug)]
+struct ArgsForPrecompile {
+  /// Path to .wasm file
+  wasm: PathBuf,
+  
+  /// Path to write .cwasm file to. Defaults to the .wasm file's path with a
+  /// .cwasm extension
+  #[clap(short, long)]
+  output: Option<PathBuf>,
   
-  generator_names: Vec<String>,
+  #[command(flatten)]
+  cache: CacheArgs,
 }
 
-impl EMGModule {
-  fn from_file(wasm: PathBuf) -> Self {
-    let engine = wasmtime::Engine::default();
-    
-    let module = match wasmtime::Module::from_file(&engine, wasm) {
-      Ok(m) => m,
-      Err(e) => fail(emg::ErrorCode::WebAssemblyCompile,
-        format!("Unable to compile .wasm file: {:?}", e)),
-    };
-    
-    // The "store" seems to be wasmtime's container for instance data
-    let mut store = wasmtime::Store::new(&engine, ());
-    
-    let instance = match wasmtime::Instance::new(&mut store, &module, &[]) {
-      Ok(i) => i,
-      Err(e) => fail(emg::ErrorCode::WebAssemblyInstance,
-        format!("Unable to instantiate WebAssembly module: {:?}", e)),
-    };
-    
-    let mut result = Self { engine, module, store, instance,
-      generator_names: Vec::new() };
-    
-    result.validate_pointer_accessor("model_pointer");
-    result.validate_pointer_accessor("model_size");
-    
-    let mut possible_model_generators = Vec::new();
-    
-    for export in result.instance.exports(&mut result.store) {
-      if export.name().starts_with("gen_") {
-        // Converting to String "launders" the name to break all links with the
-        // original string. Required by borrow checker
-        possible_model_generators.push(String::from(export.name()));
-      }
+/// Loads a .wasm file, or a .cwasm file from precompile. Only files named
+/// .cwasm are loaded as precompiled, so that a .wasm file cannot smuggle in
+/// machine code
+fn load_module(path: &Path, cache: &CacheArgs) -> Result<EMGModule,
+HostError> {
+  match path.extension() {
+    // Safe because the user asked for a .cwasm file by name, which like an
+    // executable is only as trustworthy as its source
+    Some(extension) if extension == "cwasm" => unsafe {
+      EMGModule::from_precompiled_file(path)
+    },
+    _ => EMGModule::from_file(path, &cache.into()),
+  }
+}
+
+/// Removes the messages logged during the last generate() call, and prints
+/// them along with the call's diagnostics if verbose
+fn report_call(emg_module: &mut EMGModule, verbose: bool) -> Vec<LogMessage> {
+  let logs = emg_module.take_logs();
+  
+  if verbose {
+    let diagnostics = emg_module.diagnostics();
+    eprintln!("Extracted arguments for passing through to model generator: \
+      {:?}", diagnostics.arguments);
+    for log in &logs {
+      eprintln!("[{}] {}", log.level, log.message);
     }
-    
-    // Generator validation must be done outside the loop in which possible
-    // generators are found due to borrowing issues related to the store
-    for name in possible_model_generators {
-      result.validate_model_generator(name.as_str());
-      result.generator_names.push(name);
+    if let Some(fuel) = diagnostics.fuel_consumed {
+      eprintln!("Fuel consumed: {}", fuel);
+    }
+    if let Some(pointer) = diagnostics.pointer {
+      eprintln!("Got pointer: {}", pointer);
+    }
+    if let Some(size) = diagnostics.size {
+      eprintln!("Got size: {}", size);
     }
-    
-    result
   }
   
-  fn validate_model_generator(&mut self, name: &str) {
-    let export = match self.instance.get_export(&mut self.store, name) {
-      Some(function) => function,
-      None => fail(emg::ErrorCode::ModelGeneratorNotFound, format!(".wasm file \
-        does not contain model generator `{}`", name)),
-    };
-    
-    let generator = match export.into_func() {
-      Some(function) => function,
-      None => fail(emg::ErrorCode::ModuleNotEMG, format!(".wasm file is not a \
-          valid emg module: export `{}` must be a function", name)),
-    };
-    
-    if generator.ty(&self.store).results().len() != 1 {
-      fail(emg::ErrorCode::ModuleNotEMG, format!(".wasm file is not a valid \
-        emg module: function `{}()` must return one result", name));
+  logs
+}
+
+/// Loads the .wasm file and runs the model generator
+fn run_gen(args: &ArgsForGen) -> Result<Vec<u8>, HostError> {
+  let mut emg_module = load_module(&args.wasm, &args.cache)?;
+  
+  let start = std::time::Instant::now();
+  let result = emg_module.generate(&args.generator, &args.parameters,
+    &args.named_parameters, &Limits::from(&args.limits));
+  report_call(&mut emg_module, args.verbose);
+  
+  // Printed even if generation fails, since memory use may be why
+  if args.stats {
+    eprintln!("Generation time: {} ms", start.elapsed().as_millis());
+    eprintln!("Peak memory: {} bytes", emg_module.peak_memory());
+    eprintln!("Final memory: {} bytes", emg_module.memory_size());
+    if let Ok(glb) = &result {
+      eprintln!("Output .glb size: {} bytes", glb.len());
     }
-    // .unwrap() acceptable here because the length is asserted == 1
-    match generator.ty(&self.store).results().next().unwrap() {
-      wasmtime::ValType::I32 => {},
-      _ => fail(emg::ErrorCode::ModuleNotEMG, format!(".wasm file is not a \
-        valid emg module: function `{}()` must return a 32-bit integer", name)),
+  }
+  
+  result
+}
+
+/// Format given with --format or --external-buffers, or else inferred from the
+/// output file's extension
+fn output_format(args: &ArgsForGen, path: Option<&Path>) -> Format {
+  if let Some(format) = &args.format {
+    return format.into();
+  }
+  
+  if args.external_buffers {
+    return Format::GLTFSeparate;
+  }
+  
+  infer_format(path)
+}
+
+/// Format implied by an output file's extension
+fn infer_format(path: Option<&Path>) -> Format {
+  match path.and_then(|path| path.extension()).and_then(|e| e.to_str()) {
+    Some("gltf") => Format::GLTF,
+    _ => Format::GLB,
+  }
+}
+
+/// URI of the .bin file written next to `path` for Format::GLTFSeparate.
+/// Relative, so that the .gltf and .bin can be moved together
+fn bin_uri(format: &Format, path: &Path) -> Option<String> {
+  match format {
+    Format::GLTFSeparate => path.with_extension("bin").file_name()
+      .map(|name| name.to_string_lossy().into_owned()),
+    _ => None,
+  }
+}
+
+/// Formats a model and writes it to `path`, along with a .bin file for
+/// Format::GLTFSeparate
+fn write_output(format: &Format, path: &Path, glb: Vec<u8>) ->
+Result<(), HostError> {
+  let output = format_output(glb, format, bin_uri(format, path).as_deref())?;
+  write_formatted(path, output)
+}
+
+/// Writes output from format_output() to `path`, and its BIN chunk (if
+/// separate) to a .bin file next to it
+fn write_formatted(path: &Path, (model, bin): (Vec<u8>, Option<Vec<u8>>)) ->
+Result<(), HostError> {
+  // The .bin is written first, so the .gltf never refers to a missing file
+  if let Some(bin) = bin {
+    write_atomically(&path.with_extension("bin"), &bin)?;
+  }
+  write_atomically(path, &model)
+}
+
+/// Key for a model in the output cache. Covers everything that affects the
+/// output, including the emg version since formatting may change
+fn output_cache_key(wasm: &[u8], args: &ArgsForGen, format: &Format,
+bin_uri: Option<&str>) -> String {
+  use sha2::Digest;
+  
+  // Can .unwrap() because OutputFormat has no skipped variants
+  let format = clap::ValueEnum::to_possible_value(&OutputFormat::from(format))
+    .unwrap();
+  // Can .unwrap() because lists of strings are always serializable
+  let parameters = serde_json::to_vec(&(&args.parameters,
+    &args.named_parameters)).unwrap();
+  
+  let mut hasher = sha2::Sha256::new();
+  for part in [env!("CARGO_PKG_VERSION").as_bytes(), wasm,
+    args.generator.as_bytes(), &parameters, format.get_name().as_bytes(),
+    bin_uri.unwrap_or("").as_bytes()] {
+    // Lengths are included so that parts cannot run together
+    hasher.update((part.len() as u64).to_le_bytes());
+    hasher.update(part);
+  }
+  
+  format!("{:x}", hasher.finalize())
+}
+
+/// Reads output from the cache, if all of its files are present
+fn read_cached(path: &Path, separate: bool) ->
+Option<(Vec<u8>, Option<Vec<u8>>)> {
+  let model = std::fs::read(path).ok()?;
+  let bin = if separate {
+    Some(std::fs::read(path.with_extension("bin")).ok()?)
+  } else {
+    None
+  };
+  
+  touch_cache_entry(path);
+  if separate {
+    touch_cache_entry(&path.with_extension("bin"));
+  }
+  
+  Some((model, bin))
+}
+
+/// Runs the model generator and formats its output, or reads the output from
+/// the cache without loading the module if it has been generated before
+fn cached_gen(args: &ArgsForGen, format: &Format, bin_uri: Option<&str>) ->
+Result<(Vec<u8>, Option<Vec<u8>>), HostError> {
+  let generate = || run_gen(args)
+    .and_then(|glb| format_output(glb, format, bin_uri));
+  
+  let directory = match (args.cache.no_cache,
+    CacheOptions::from(&args.cache).directory()) {
+    (false, Some(directory)) => directory,
+    _ => return generate(),
+  };
+  
+  let wasm = match std::fs::read(&args.wasm) {
+    Ok(b) => b,
+    Err(e) => return Err(HostError::IO { path: Some(args.wasm.clone()),
+      message: format!("Unable to read {}: {}", args.wasm.display(), e),
+      source: Some(e) }),
+  };
+  
+  let outputs = directory.join("outputs");
+  let path = outputs.join(output_cache_key(&wasm, args, format, bin_uri));
+  
+  if let Some(output) = read_cached(&path, bin_uri.is_some()) {
+    if args.verbose {
+      eprintln!("Read cached output from {}", path.display());
+    }
+    if args.stats {
+      eprintln!("Output read from cache");
     }
+    
+    return Ok(output);
   }
   
-  fn validate_pointer_accessor(&mut self, name: &str) {
-    let export = match self.instance.get_export(&mut self.store, name) {
-      Some(function) => function,
-      None => fail(emg::ErrorCode::ModuleNotEMG, format!(".wasm file is not a \
-        valid emg module: missing required function `{}()`", name)),
-    };
+  let output = generate()?;
+  
+  // The cache is only an optimization, so failing to write it is not an error
+  let written = std::fs::create_dir_all(&outputs)
+    .map_err(|e| HostError::IO { path: None, message: e.to_string(),
+      source: Some(e) })
+    .and_then(|_| write_formatted(&path, output.clone()));
+  if let (Err(failure), true) = (written, args.verbose) {
+    eprintln!("Unable to cache output: {}", failure);
+  }
+  evict_cache(&outputs, MAX_CACHE_SIZE);
+  
+  Ok(output)
+}
+
+/// How often the .wasm file is checked for changes in watch mode
+const WATCH_INTERVAL: std::time::Duration =
+  std::time::Duration::from_millis(250);
+
+/// Regenerates the model every time the .wasm file changes. Never returns;
+/// errors are reported and the last good output is left in place
+fn watch(args: &ArgsForGen, output: &Path) -> ! {
+  eprintln!("Watching {} (press Ctrl+C to stop)", args.wasm.display());
+  
+  // Size is checked along with modification time because some filesystems
+  // only record modification times to the second
+  let mut last_seen = None;
+  
+  loop {
+    let seen = std::fs::metadata(&args.wasm).ok()
+      .and_then(|m| m.modified().ok().map(|modified| (modified, m.len())));
     
-    let accessor = match export.into_func() {
-      Some(function) => function,
-      None => fail(emg::ErrorCode::ModuleNotEMG, format!(".wasm file is not a \
-          valid emg module: export `{}` must be a function", name)),
+    if seen.is_some() && seen != last_seen {
+      last_seen = seen;
+      
+      let start = std::time::Instant::now();
+      let format = output_format(args, Some(output));
+      match run_gen(args).and_then(|glb| write_output(&format, output, glb)) {
+        Ok(_) => eprintln!("Wrote {} in {} ms", output.display(),
+          start.elapsed().as_millis()),
+        Err(failure) => eprintln!("Error: {} (keeping previous output)",
+          failure),
+      }
+    }
+    
+    std::thread::sleep(WATCH_INTERVAL);
+  }
+}
+
+/// Prints the documentation for a model generator and its parameters
+fn help_generator(args: &ArgsForGen) -> Result<(), HostError> {
+  let emg_module = load_module(&args.wasm, &args.cache)?;
+  
+  let generator = match emg_module.generator(&args.generator) {
+    Some(g) => g,
+    None => return Err(HostError::GeneratorNotFound {
+      generator: args.generator.clone(),
+      message: format!(".wasm file does not contain model generator `{}`",
+      args.generator) }),
+  };
+  
+  println!("{}", generator.signature());
+  if !generator.doc.is_empty() {
+    println!("\n{}", generator.doc);
+  }
+  
+  if !generator.parameters.is_empty() {
+    println!("\nParameters:");
+  }
+  for (i, parameter) in generator.parameters.iter().enumerate() {
+    let mut summary = match &parameter.name {
+      Some(name) => format!("  {}: {}", name, parameter.type_name()),
+      None => format!("  {}: {}", i + 1, parameter.type_name()),
     };
     
-    if accessor.ty(&self.store).params().len() != 0 {
-      fail(emg::ErrorCode::ModuleNotEMG, format!(".wasm file is not a valid \
-        emg module: function `{}()` must not accept any arguments", name));
+    if let Some(default) = parameter.default_summary() {
+      summary += &format!(" = {}", default);
     }
-    if accessor.ty(&self.store).results().len() != 1 {
-      fail(emg::ErrorCode::ModuleNotEMG, format!(".wasm file is not a valid \
-        emg module: function `{}()` must return one result", name));
+    if let Some(range) = parameter.range() {
+      summary += &format!(" ({})", range);
     }
-    // .unwrap() acceptable here because the length is asserted == 1
-    match accessor.ty(&self.store).results().next().unwrap() {
-      wasmtime::ValType::I32 => {},
-      _ => fail(emg::ErrorCode::ModuleNotEMG, format!(".wasm file is not a \
-        valid emg module: function `{}()` must return a 32-bit integer", name)),
+    if !parameter.variants.is_empty() {
+      summary += &format!(" (one of {})", parameter.variants.join(", "));
+    }
+    
+    println!("{}", summary);
+    
+    for line in parameter.doc.lines() {
+      println!("      {}", line);
     }
   }
+  
+  Ok(())
 }
 
-struct ChunkMetadata {
-  start: u32,
-  end: u32,
+fn gen(args: ArgsForGen) -> Result<(), Failure> {
+  if args.help_generator {
+    return Ok(help_generator(&args)?);
+  }
+  
+  if let (Format::GLTFSeparate, None) = (output_format(&args, None),
+    &args.output) {
+    return Err(Failure::Usage("gltf-separate output requires --output, since \
+      the .bin file cannot be written to stdout".into()));
+  }
+  
+  if let (true, Some(output)) = (args.watch, &args.output) {
+    watch(&args, output);
+  }
+  
+  let format = output_format(&args, args.output.as_deref());
+  let bin_uri = args.output.as_deref().and_then(|path| bin_uri(&format, path));
+  let output = cached_gen(&args, &format, bin_uri.as_deref())?;
+  
+  match &args.output {
+    Some(path) => write_formatted(path, output)?,
+    // Writing fails if stdout is a pipe that was closed early, as with
+    // `emg gen ... | head`
+    None => std::io::stdout().write_all(&output.0).map_err(|e|
+      HostError::IO { path: None,
+        message: format!("Unable to write to stdout: {}", e),
+        source: Some(e) })?,
+  }
+  
+  Ok(())
 }
 
-struct GLBMetadata {
-  json: ChunkMetadata,
-  bin: Option<ChunkMetadata>,
+/// Lists the values of a --sweep, as its parameter name and values
+fn parse_sweep(sweep: &str) -> Result<(String, Vec<String>), HostError> {
+  let malformed = || HostError::Parameter {
+    code: emg::ErrorCode::ParameterType, generator: None, index: None,
+    message: format!("sweep `{}` should be written as \
+    `name=start..end:step` or `name=value,value,...`", sweep) };
+  
+  let (name, values) = sweep.split_once('=').ok_or_else(malformed)?;
+  
+  let (start, rest) = match values.split_once("..") {
+    Some(split) => split,
+    None => return Ok((String::from(name),
+      values.split(',').map(String::from).collect())),
+  };
+  let (end, step) = rest.split_once(':').unwrap_or((rest, "1"));
+  
+  let parse = |text: &str| text.parse::<f64>().ok()
+    .filter(|number| number.is_finite());
+  let (start_number, end_number, step_number) =
+    match (parse(start), parse(end), parse(step)) {
+      (Some(start), Some(end), Some(step)) if step > 0.0 && start <= end =>
+        (start, end, step),
+      _ => return Err(malformed()),
+    };
+  
+  // Values are printed with as many decimal places as were given, so that
+  // floating point error does not show up in values or file names
+  let decimals = [start, step].iter()
+    .map(|text| text.split_once('.').map_or(0, |(_, decimals)| decimals.len()))
+    .max().unwrap_or(0);
+  
+  // The small margin keeps the end value despite floating point error
+  let count = ((end_number - start_number) / step_number + 1e-9) as usize + 1;
+  
+  Ok((String::from(name), (0..count).map(|i| format!("{:.*}", decimals,
+    start_number + i as f64 * step_number)).collect()))
 }
 
-impl GLBMetadata {
-  fn from_glb(glb: &[u8]) -> Self {
-    if glb.len() < 12 {
-      fail(emg::ErrorCode::OutputNotGLB, format!("Generated output is too \
-        small ({} bytes) to contain required .glb header (12 bytes)",
-        glb.len()))
-    }
+/// Every combination of the values of several sweeps, as `name=value` pairs.
+/// The first sweep changes slowest
+fn sweep_combinations(sweeps: &[(String, Vec<String>)]) -> Vec<Vec<String>> {
+  let mut combinations = vec![Vec::new()];
+  
+  for (name, values) in sweeps {
+    combinations = combinations.into_iter().flat_map(|combination| {
+      values.iter().map(move |value| {
+        let mut extended = combination.clone();
+        extended.push(format!("{}={}", name, value));
+        extended
+      })
+    }).collect();
+  }
+  
+  combinations
+}
+
+#[derive(serde::Serialize, Debug)]
+struct BatchManifest {
+  wasm: String,
+  generator: String,
+  models: Vec<BatchModel>,
+}
+
+#[derive(serde::Serialize, Debug)]
+struct BatchModel {
+  /// Relative to the manifest
+  file: String,
+  
+  /// Value of every parameter, including defaults. Keyed by name, or by
+  /// position (starting from 1) for unnamed parameters
+  parameters: serde_json::Map<String, serde_json::Value>,
+  
+  /// Only present if this model could not be generated
+  #[serde(skip_serializing_if = "Option::is_none")]
+  error: Option<String>,
+}
+
+fn batch(args: ArgsForBatch) -> Result<(), Failure> {
+  let sweeps = args.sweep.iter().map(|sweep| parse_sweep(sweep))
+    .collect::<Result<Vec<_>, _>>()?;
+  
+  let mut emg_module = load_module(&args.wasm, &args.cache)?;
+  
+  let metadata = match emg_module.generator(&args.generator) {
+    Some(m) => m.clone(),
+    None => return Err(HostError::GeneratorNotFound {
+      generator: args.generator.clone(),
+      message: format!(".wasm file does not contain model generator `{}`",
+      args.generator) }.into()),
+  };
+  
+  let template = args.name.clone().unwrap_or_else(|| {
+    sweeps.iter().fold(String::from("{generator}"), |template, (name, _)|
+      template + "-{" + name + "}")
+  });
+  let format = Format::from(&args.format);
+  let extension = match format {
+    Format::GLB => "glb",
+    _ => "gltf",
+  };
+  
+  let mut models = Vec::new();
+  
+  // Everything is resolved before generating, so that mistakes in the sweeps
+  // or template are found without waiting for any models
+  for (index, sweep_parameters) in sweep_combinations(&sweeps).into_iter()
+    .enumerate() {
+    let mut named_parameters = args.named_parameters.clone();
+    named_parameters.extend(sweep_parameters);
     
-    // Can .unwrap() because .glb size was just checked
-    let magic = String::from_utf8_lossy(glb[0..4].try_into().unwrap());
-    let version = u32::from_le_bytes(glb[ 4.. 8].try_into().unwrap());
-    let length  = u32::from_le_bytes(glb[ 8..12].try_into().unwrap());
+    let values = metadata.resolve_parameters(&[], &named_parameters)?;
     
-    if magic != "glTF" {
-      fail(emg::ErrorCode::OutputNotGLB, format!("Generated output does not \
-        begin with magic bytes `glTF` required in .glb files (has `{}` \
-        instead)", magic));
+    let mut file = template.replace("{generator}", &args.generator)
+      .replace("{index}", &index.to_string());
+    let mut parameters = serde_json::Map::new();
+    for (i, (parameter, value)) in metadata.parameters.iter().zip(&values)
+      .enumerate() {
+      let key = parameter.name.clone().unwrap_or((i + 1).to_string());
+      // Values may contain path separators, which would escape --out-dir
+      file = file.replace(&format!("{{{}}}", key), &value.replace(['/', '\\'],
+        "_"));
+      parameters.insert(key, serde_json::Value::from(value.as_str()));
     }
+    file += ".";
+    file += extension;
     
-    if version != 2 {
-      fail(emg::ErrorCode::NotImplemented, format!("Generated output gives a \
-        .glb container version of {}, but this tool only supports version 2",
-        version));
+    if models.iter().any(|model: &(Vec<String>, BatchModel)|
+      model.1.file == file) {
+      return Err(HostError::IO { path: Some(args.out_dir.join(&file)),
+        message: format!("more than one model would be written to `{}`. Use \
+        --name to give each a different name", file), source: None }.into());
     }
     
-    if length != glb.len() as u32 {
-      fail(emg::ErrorCode::OutputNotGLB, format!("Header in generated output \
-        gives a length of {} bytes, but output is {} bytes", length,
-        glb.len()));
-    }
+    models.push((named_parameters, BatchModel { file, parameters,
+      error: None }));
+  }
+  
+  match std::fs::create_dir_all(&args.out_dir) {
+    Ok(_) => {},
+    Err(e) => return Err(HostError::IO { path: Some(args.out_dir.clone()),
+      message: format!("Unable to create {}: {}", args.out_dir.display(), e),
+      source: Some(e) }.into()),
+  }
+  
+  // Failed models are recorded in the manifest instead of stopping the batch,
+  // and the first failure's code is the exit code
+  let mut failed = 0;
+  let mut first_failure = None;
+  
+  for (i, (named_parameters, model)) in models.iter_mut().enumerate() {
+    let path = args.out_dir.join(&model.file);
     
-    let json_length = Self::validate_chunk(glb[12..].try_into().unwrap(),
-      String::from("JSON"));
+    // Each run gets a fresh instance, so that no state leaks between models
+    let reinstantiated = if i == 0 {
+      Ok(())
+    } else {
+      emg_module.reinstantiate()
+    };
     
-    let json_metadata = ChunkMetadata { start: 20, end: 12 + json_length };
+    let result = reinstantiated.and_then(|_| {
+      let result = emg_module.generate(&args.generator, &[], named_parameters,
+        &Limits::from(&args.limits));
+      report_call(&mut emg_module, args.verbose);
+      result
+    }).and_then(|glb| write_output(&format, &path, glb));
     
-    if length == json_metadata.end {
-      return GLBMetadata { json: json_metadata, bin: None };
+    match result {
+      Ok(_) => eprintln!("Wrote {}", path.display()),
+      Err(failure) => {
+        eprintln!("Error: {} ({})", failure, model.file);
+        model.error = Some(failure.to_string());
+        failed += 1;
+        first_failure.get_or_insert(failure);
+      },
     }
+  }
+  
+  let total = models.len();
+  let manifest = BatchManifest {
+    wasm: args.wasm.display().to_string(),
+    generator: args.generator.clone(),
+    models: models.into_iter().map(|(_, model)| model).collect(),
+  };
+  // Can .unwrap() because the manifest contains only strings and maps
+  let manifest = serde_json::to_vec_pretty(&manifest).unwrap();
+  write_atomically(&args.out_dir.join("manifest.json"), &manifest)?;
+  
+  match first_failure {
+    Some(first) => Err(Failure::Models { failed, total, first }),
+    None => Ok(()),
+  }
+}
+
+/// Lists models to generate. Read from TOML, where each model is a [[model]]
+/// table
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct BuildFile {
+  #[serde(rename = "model", default)]
+  models: Vec<BuildModel>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct BuildModel {
+  wasm: PathBuf,
+  generator: String,
+  output: PathBuf,
+  
+  /// Same values as --format. Inferred from the output path if not given
+  format: Option<String>,
+  
+  #[serde(default)]
+  parameters: BuildParameters,
+}
+
+/// Either a list of positional parameters or a table of named parameters.
+/// Values may be strings, numbers, or bools
+#[derive(serde::Deserialize, Debug)]
+#[serde(untagged)]
+enum BuildParameters {
+  Positional(Vec<toml::Value>),
+  Named(toml::Table),
+}
+
+impl Default for BuildParameters {
+  fn default() -> Self {
+    Self::Positional(Vec::new())
+  }
+}
+
+/// A model from a build file, checked and ready to generate
+struct BuildJob {
+  wasm: PathBuf,
+  generator: String,
+  output: PathBuf,
+  format: Format,
+  parameters: Vec<String>,
+  named_parameters: Vec<String>,
+}
+
+/// Converts a parameter value from a build file into the text emg gen would
+/// take for it
+fn build_parameter_text(value: &toml::Value, model: usize) ->
+Result<String, Failure> {
+  match value {
+    toml::Value::String(text) => Ok(text.clone()),
+    toml::Value::Integer(number) => Ok(number.to_string()),
+    toml::Value::Float(number) => Ok(number.to_string()),
+    toml::Value::Boolean(boolean) => Ok(boolean.to_string()),
+    _ => Err(Failure::BuildFile(format!("model {} in build file has a \
+      parameter that is not a string, number, or bool", model + 1))),
+  }
+}
+
+/// Reads a build file and resolves its paths and formats
+fn read_build_file(path: &Path) -> Result<Vec<BuildJob>, Failure> {
+  let text = match std::fs::read_to_string(path) {
+    Ok(t) => t,
+    Err(e) => return Err(HostError::IO { path: Some(path.to_path_buf()),
+      message: format!("Unable to read {}: {}", path.display(), e),
+      source: Some(e) }.into()),
+  };
+  
+  let build_file: BuildFile = match toml::from_str(&text) {
+    Ok(b) => b,
+    Err(e) => return Err(Failure::BuildFile(format!("Unable to read build \
+      file {}: {}", path.display(), e))),
+  };
+  
+  // Can .unwrap() because a file that was just read has a parent
+  let directory = path.parent().unwrap();
+  
+  build_file.models.into_iter().enumerate().map(|(i, model)| {
+    let output = directory.join(&model.output);
     
-    let bin_length = Self::validate_chunk(
-      glb[json_metadata.end as usize..].try_into().unwrap(),
-      String::from("BIN\0"));
+    let format = match &model.format {
+      Some(format) => match <OutputFormat as clap::ValueEnum>::from_str(
+        format, false) {
+        Ok(f) => Format::from(&f),
+        Err(_) => return Err(Failure::BuildFile(format!("model {} in build \
+          file has unknown format `{}`", i + 1, format))),
+      },
+      None => infer_format(Some(&output)),
+    };
     
-    let bin_metadata = ChunkMetadata { start: json_metadata.end + 8, end:
-      json_metadata.end + bin_length };
+    let (parameters, named_parameters) = match &model.parameters {
+      BuildParameters::Positional(values) => (values.iter()
+        .map(|value| build_parameter_text(value, i))
+        .collect::<Result<_, _>>()?, Vec::new()),
+      BuildParameters::Named(table) => (Vec::new(), table.iter()
+        .map(|(name, value)| build_parameter_text(value, i)
+          .map(|text| format!("{}={}", name, text)))
+        .collect::<Result<_, _>>()?),
+    };
     
-    if bin_metadata.end < length {
-      fail(emg::ErrorCode::NotImplemented, format!("Generated output contains \
-        additional space ({} bytes) after the JSON and BIN chunks, but this \
-        tool does not support any other chunk types", length -
-        bin_metadata.end));
+    Ok(BuildJob { wasm: directory.join(&model.wasm),
+      generator: model.generator, output, format, parameters,
+      named_parameters })
+  }).collect()
+}
+
+/// Generates a model from a build file and writes it
+fn run_build_job(emg_module: &EMGModule, job: &BuildJob, args: &ArgsForBuild)
+-> Result<(), HostError> {
+  let mut emg_module = emg_module.fork()?;
+  let result = emg_module.generate(&job.generator, &job.parameters,
+    &job.named_parameters, &Limits::from(&args.limits));
+  report_call(&mut emg_module, args.verbose);
+  let glb = result?;
+  
+  if let Some(parent) = job.output.parent() {
+    match std::fs::create_dir_all(parent) {
+      Ok(_) => {},
+      Err(e) => return Err(HostError::IO { path: Some(parent.to_path_buf()),
+        message: format!("Unable to create {}: {}", parent.display(), e),
+        source: Some(e) }),
     }
-    
-    GLBMetadata { json: json_metadata, bin: Some(bin_metadata) }
   }
   
-  /// Returns size of chunk if valid, otherwise exits
-  fn validate_chunk(chunk: &[u8], expected_type: String) -> u32 {
-    if chunk.len() < 8 {
-      fail(emg::ErrorCode::OutputNotGLB, format!("Genrated out does not have \
-        enough space remaining for `{}` header ({} bytes remain but .glb chunk \
-        headers are 8 bytes)", expected_type, chunk.len()));
-    }
-    
-    // Can .unwrap() because .glb size was just checked
-    let data_length = u32::from_le_bytes(chunk[0..4].try_into().unwrap());
-    let chunk_type = String::from_utf8_lossy(chunk[4..8].try_into().unwrap());
-    
-    if chunk_type != expected_type {
-      fail(emg::ErrorCode::OutputNotGLB, format!("Found `{}` chunk in \
-        generated output where `{}` chunk was expected", chunk_type,
-        expected_type));
-    }
-    
-    if (chunk.len() as u32) < (8 + data_length) {
-      fail(emg::ErrorCode::OutputNotGLB, format!("`{}` chunk header in \
-        generated output gives a length of {} bytes, but output is only long \
-        enough for up to {} bytes", chunk_type, data_length, chunk.len() - 8));
+  write_output(&job.format, &job.output, glb)
+}
+
+fn build(args: ArgsForBuild) -> Result<(), Failure> {
+  let jobs = read_build_file(&args.build_file)?;
+  
+  // Each .wasm file is compiled once, and every job gets its own instance
+  let mut emg_modules = HashMap::new();
+  for job in &jobs {
+    if !emg_modules.contains_key(&job.wasm) {
+      emg_modules.insert(job.wasm.clone(),
+        load_module(&job.wasm, &args.cache)?);
     }
     
-    if data_length % 4 > 0 {
-      fail(emg::ErrorCode::OutputNotGLB, format!("`{}` chunk in generated \
-        output is {} bytes, but all chunks in .glb files must be multiples of \
-        4 bytes", chunk_type, data_length + 8));
+    // Indexing cannot panic because the module was just inserted if missing
+    if emg_modules[&job.wasm].generator(&job.generator).is_none() {
+      return Err(HostError::GeneratorNotFound {
+        generator: job.generator.clone(),
+        message: format!("{} does not contain model generator `{}`",
+        job.wasm.display(), job.generator) }.into());
     }
-    
-    data_length + 8
   }
-}
-
-fn gen(args: ArgsForGen) {
-  let emg_module = EMGModule::from_file(args.wasm);
-  let mut store = emg_module.store;
-  let instance = emg_module.instance;
   
-  let generator = match instance.get_func(&mut store,
-    (String::from("gen_") + &args.generator).as_str(),
-  ) {
-    Some(f) => f,
-    None => fail(emg::ErrorCode::ModelGeneratorNotFound, format!(".wasm file \
-      does not contain model generator `{}`", args.generator)),
-  };
+  let threads = args.jobs.unwrap_or_else(|| {
+    std::thread::available_parallelism().map_or(1, |threads| threads.get())
+  }).max(1);
   
-  let parameter_count = generator.ty(&store).params().len();
-  if args.parameters.len() != parameter_count {
-    fail(emg::ErrorCode::ParameterCount,
-      format!("model generator expects {} parameters, but {} were given",
-      parameter_count, args.parameters.len()));
-  }
+  // Threads take the next job from here until none are left
+  let next_job = std::sync::atomic::AtomicUsize::new(0);
   
-  let mut generator_args: Vec<wasmtime::Val> = Vec::new();
-  let mut i = 0;
-  for parameter in generator.ty(&store).params() {
-    match parameter {
-      wasmtime::ValType::I32 => {
-        match args.parameters[i].parse::<i32>() {
-          Ok(v) => generator_args.push(wasmtime::Val::from(v)),
-          Err(_) => fail(emg::ErrorCode::ParameterType,
-            format!("model generator parameter {} (set to `{}`) should be a \
-            32-bit integer", i + 1, args.parameters[i])),
-        }
-      },
-      
-      wasmtime::ValType::I64 => {
-        match args.parameters[i].parse::<i64>() {
-          Ok(v) => generator_args.push(wasmtime::Val::from(v)),
-          Err(_) => fail(emg::ErrorCode::ParameterType,
-            format!("model generator parameter {} (set to `{}`) should be a \
-            64-bit integer", i + 1, args.parameters[i])),
-        }
-      },
-      
-      wasmtime::ValType::F32 => {
-        match args.parameters[i].parse::<f32>() {
-          Ok(v) => generator_args.push(wasmtime::Val::from(v)),
-          Err(_) => fail(emg::ErrorCode::ParameterType,
-            format!("model generator parameter {} (set to `{}`) should be a \
-            32-bit floating-point value", i + 1, args.parameters[i])),
+  let mut results: Vec<(usize, Result<(), HostError>)> =
+    std::thread::scope(|scope| {
+      let workers: Vec<_> = (0..threads).map(|_| scope.spawn(|| {
+        let mut results = Vec::new();
+        
+        loop {
+          let i = next_job.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+          let Some(job) = jobs.get(i) else { break };
+          
+          let result = run_build_job(&emg_modules[&job.wasm], job, &args);
+          match &result {
+            Ok(_) => eprintln!("Wrote {}", job.output.display()),
+            Err(failure) => eprintln!("Error: {} ({})", failure,
+              job.output.display()),
+          }
+          results.push((i, result));
         }
-      },
+        
+        results
+      })).collect();
       
-      wasmtime::ValType::F64 => {
-        match args.parameters[i].parse::<f64>() {
-          Ok(v) => generator_args.push(wasmtime::Val::from(v)),
-          Err(_) => fail(emg::ErrorCode::ParameterType,
-            format!("model generator parameter {} (set to `{}`) should be a \
-            64-bit floating-point value", i + 1, args.parameters[i])),
+      // Can .unwrap() because workers report failures instead of panicking
+      workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+    });
+  
+  // Failed models do not stop the build, and the first failure in the build
+  // file's order is the exit code
+  results.sort_by_key(|(i, _)| *i);
+  let total = results.len();
+  let failures: Vec<HostError> = results.into_iter()
+    .filter_map(|(_, result)| result.err()).collect();
+  let failed = failures.len();
+  
+  match failures.into_iter().next() {
+    Some(first) => Err(Failure::Models { failed, total, first }),
+    None => Ok(()),
+  }
+}
+
+const VIEWER_HTML: &str = include_str!("viewer.html");
+
+/// Decodes %XX escapes and + signs in a URL query component
+fn percent_decode(encoded: &str) -> String {
+  let bytes = encoded.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  
+  let mut i = 0;
+  while i < bytes.len() {
+    match bytes[i] {
+      b'+' => decoded.push(b' '),
+      b'%' if i + 2 < bytes.len() => {
+        // Can .unwrap() because from_utf8() only fails on invalid characters,
+        // which do not parse as hexadecimal anyway
+        match u8::from_str_radix(std::str::from_utf8(&bytes[i + 1..i + 3])
+          .unwrap_or(""), 16) {
+          Ok(byte) => {
+            decoded.push(byte);
+            i += 2;
+          },
+          Err(_) => decoded.push(b'%'),
         }
       },
-      
-      _ => fail(emg::ErrorCode::ParameterType,
-        "emg model generators only support parameters of type i32, \
-        i64, f32, or f64"),
-    };
+      byte => decoded.push(byte),
+    }
     
     i += 1;
   }
   
-  if args.verbose {
-    eprintln!("Extracted arguments for passing through to model generator: \
-      {:?}", generator_args);
+  String::from_utf8_lossy(&decoded).into_owned()
+}
+
+type Response = tiny_http::Response<std::io::Cursor<Vec<u8>>>;
+
+fn content_response(body: Vec<u8>, content_type: &str) -> Response {
+  // Can .unwrap() because all content types used here are ASCII
+  tiny_http::Response::from_data(body).with_header(
+    tiny_http::Header::from_bytes("Content-Type", content_type).unwrap())
+}
+
+fn failure_response(failure: HostError, verbose: bool) -> Response {
+  if verbose { eprintln!("Error: {}", failure) }
+  
+  let status = match failure {
+    HostError::GeneratorNotFound { .. } => 404,
+    HostError::Parameter { .. } => 400,
+    _ => 500,
+  };
+  
+  // Can .unwrap() because the error code header is an ASCII number
+  content_response(format!("Error: {}\n", failure).into_bytes(),
+    "text/plain; charset=utf-8")
+  .with_status_code(status)
+  .with_header(tiny_http::Header::from_bytes("X-EMG-Error-Code",
+    failure.code().to_string()).unwrap())
+}
+
+fn serve_inspect(args: &ArgsForServe) -> Result<Vec<u8>, HostError> {
+  let mut emg_module = load_module(&args.wasm, &args.cache)?;
+  let description = describe(&mut emg_module, &args.wasm)?;
+  
+  // Can .unwrap() because a json! value is always serializable
+  Ok(serde_json::to_vec(&description).unwrap())
+}
+
+/// Positional parameters are given as `@=value`, since `@` cannot be part of
+/// a parameter name. Any other `name=value` pair is a named parameter
+/// Anything the model generator logs is added to `logs`, even if it fails
+fn serve_model(args: &ArgsForServe, generator: &str, query: &str,
+logs: &mut Vec<LogMessage>) -> Result<Vec<u8>, HostError> {
+  let mut parameters = Vec::new();
+  let mut named_parameters = Vec::new();
+  
+  for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+    let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+    
+    // Browsers send @ as %40
+    if percent_decode(name) == "@" {
+      parameters.push(percent_decode(value));
+    } else {
+      named_parameters.push(percent_decode(pair));
+    }
   }
   
-  let mut result = [wasmtime::Val::from(0)];
-  match generator.call(&mut store, &generator_args, &mut result) {
-    Ok(_) => {},
-    Err(e) => fail(emg::ErrorCode::WebAssemblyExecution,
-      format!("WebAssembly execution failed: {:?}", e)),
-  }
-  // .unwrap() acceptable here because the type was previously asserted
-  match result[0].i32().unwrap() {
-    0 => {},
-    e => {
-      // I don't use emg::ErrorCode here because rust makes it hard to convert
-      // an integer to an enum
-      eprintln!("model generation returned error code: {}", e);
-      std::process::exit(e);
-    },
+  let mut emg_module = load_module(&args.wasm, &args.cache)?;
+  let result = emg_module.generate(generator, &parameters, &named_parameters,
+    &Limits::from(&args.limits));
+  *logs = report_call(&mut emg_module, args.verbose);
+  let glb = result?;
+  
+  // Checked here so that a broken model is reported as an error, rather than
+  // sent to the viewer as if it were a valid .glb
+  GLBMetadata::from_glb(&glb)?;
+  
+  Ok(glb)
+}
+
+/// Most JSON sent in the X-EMG-Log header. Browsers and servers commonly
+/// reject headers over 8 KiB, and base64 encoding adds a third
+const MAX_LOG_JSON: usize = 6 * 1024;
+
+/// Logs as JSON, cut to fit in MAX_LOG_JSON. The earliest messages are kept,
+/// followed by a warning saying how many were left out
+fn log_header_json(logs: &[LogMessage]) -> Vec<u8> {
+  // Can .unwrap() because LogMessage is always serializable
+  let json = serde_json::to_vec(logs).unwrap();
+  if json.len() <= MAX_LOG_JSON {
+    return json;
   }
   
-  // Can use .unwrap() because validator checked this exists
-  let get_pointer = instance.get_func(&mut store, "model_pointer").unwrap();
-  let mut pointer = [wasmtime::Val::from(0)];
-  match get_pointer.call(&mut store, &[], &mut pointer) {
-    Ok(_) => {},
-    Err(e) => fail(emg::ErrorCode::WebAssemblyExecution,
-      format!("Unable to retrieve model: {:?}", e)),
+  // Leaves room for the brackets and the warning
+  let mut size = 2 + 256;
+  let kept = logs.iter().take_while(|log| {
+    size += serde_json::to_vec(log).unwrap().len() + 1;
+    size <= MAX_LOG_JSON
+  }).count();
+  
+  let mut truncated = logs[..kept].to_vec();
+  truncated.push(LogMessage { level: String::from("Warn"),
+    message: format!("{} more log messages were not sent, because they do \
+    not fit in a header. Run emg serve with --verbose to see them on stderr",
+    logs.len() - kept) });
+  
+  serde_json::to_vec(&truncated).unwrap()
+}
+
+fn route(args: &ArgsForServe, url: &str) -> Response {
+  let (path, query) = url.split_once('?').unwrap_or((url, ""));
+  
+  if path == "/" {
+    return content_response(VIEWER_HTML.as_bytes().to_vec(),
+      "text/html; charset=utf-8");
   }
-  if args.verbose { eprintln!("Got pointer: {:?}", pointer) }
   
-  // Can use .unwrap() because validator checked this exists
-  let get_size = instance.get_func(&mut store, "model_size").unwrap();
-  let mut size = [wasmtime::Val::from(0)];
-  match get_size.call(&mut store, &[], &mut size) {
-    Ok(_) => {},
-    Err(e) => fail(emg::ErrorCode::WebAssemblyExecution,
-      format!("Unable to retrieve model: {:?}", e)),
-  }
-  if args.verbose { eprintln!("Got size: {:?}", size) }
-  
-  // Can .unwrap() because WebAssembly modules always have a "memory" export
-  let memory = instance.get_memory(&mut store, "memory").unwrap();
-  // Can .unwrap() because the types of pointer and size were asserted earlier
-  let pointer_plain_int = pointer[0].i32().unwrap() as usize;
-  let size_plain_int = size[0].i32().unwrap() as usize;
-  let memory_of_interest = &(memory.data(&store))[pointer_plain_int..pointer_plain_int + size_plain_int];
-  
-  let glb_metadata = GLBMetadata::from_glb(memory_of_interest);
-  
-  let mut parsed: serde_json::Value = match serde_json::de::from_slice(
-    &memory_of_interest[glb_metadata.json.start as usize..
-    glb_metadata.json.end as usize]) {
-    Ok(json) => json,
-    Err(e) => fail(emg::ErrorCode::OutputNotGLB,
-      format!("model generated with invalid JSON: {:?}", e)),
+  if path == "/inspect" {
+    return match serve_inspect(args) {
+      Ok(json) => content_response(json, "application/json"),
+      Err(failure) => failure_response(failure, args.verbose),
+    };
+  }
+  
+  if let Some(generator) = path.strip_prefix("/gen/") {
+    let mut logs = Vec::new();
+    let response = match serve_model(args, &percent_decode(generator), query,
+      &mut logs) {
+      Ok(glb) => content_response(glb, "model/gltf-binary"),
+      Err(failure) => failure_response(failure, args.verbose),
+    };
+    
+    // Headers must be ASCII, so the logs are sent as base64-encoded JSON for
+    // the viewer to show in the browser console
+    if logs.is_empty() {
+      return response;
+    }
+    
+    // Can .unwrap() because base64 is ASCII
+    let logs = base64::engine::general_purpose::STANDARD.encode(
+      log_header_json(&logs));
+    return response.with_header(tiny_http::Header::from_bytes("X-EMG-Log",
+      logs).unwrap());
+  }
+  
+  content_response(format!("No such page: {}\n", path).into_bytes(),
+    "text/plain; charset=utf-8").with_status_code(404)
+}
+
+fn serve(args: ArgsForServe) -> Result<(), Failure> {
+  // Loading the module once at startup catches broken .wasm files before any
+  // requests are made
+  let emg_module = load_module(&args.wasm, &args.cache)?;
+  
+  let server = match tiny_http::Server::http((args.host.as_str(), args.port)) {
+    Ok(s) => s,
+    Err(e) => return Err(HostError::IO { path: None, message: format!(
+      "Unable to listen on {}:{}: {}", args.host, args.port, e),
+      source: None }.into()),
   };
   
-  match args.format {
-    // Can .unwrap() because this JSON was just parsed, so it must be valid
-    Format::Pretty => {
-      println!("{}", serde_json::to_string_pretty(&parsed).unwrap());
-    },
-    Format::GLTF => {
-      match glb_metadata.bin {
-        None => {},
-        Some(bin_metadata) => {
-          let buffers = match parsed.get_mut("buffers") {
-            Some(v) => v,
-            None => fail(emg::ErrorCode::OutputNotGLB, "No `buffers` field \
-              present in generated output"),
-          };
-          
-          let buffer_0 = match buffers.get_mut(0) {
-            Some(v) => v,
-            None => fail(emg::ErrorCode::OutputNotGLB, "`buffers` field in \
-              generated output has no entries"),
-          };
-          
-          let buffer_0_as_object = match buffer_0.as_object_mut() {
-            Some(v) => v,
-            None => fail(emg::ErrorCode::OutputNotGLB, "`buffer[0]` field in \
-              generated output is not an object"),
-          };
-          
-          let mut base64_buffer = String::from("data:application/octet-stream;\
-            base64,");
-          
-          base64::engine::general_purpose::STANDARD.encode_string(
-            &memory_of_interest[bin_metadata.start as usize..
-            bin_metadata.end as usize],
-            &mut base64_buffer);
-          
-          buffer_0_as_object.insert(String::from("uri"),
-            serde_json::Value::String(base64_buffer));
-        },
-      }
-      
-      println!("{}", serde_json::to_string(&parsed).unwrap());
-    },
-    Format::GLB => std::io::stdout().write_all(&memory_of_interest).unwrap(),
+  eprintln!("Serving {} at http://{}:{}/", args.wasm.display(), args.host,
+    args.port);
+  eprintln!("Model generators found:");
+  for generator in emg_module.generators() {
+    eprintln!("\t{}", generator.signature());
   }
+  
+  for request in server.incoming_requests() {
+    if args.verbose { eprintln!("{} {}", request.method(), request.url()) }
+    
+    let response = route(&args, request.url());
+    
+    // A client that disconnects early is not a problem for the server
+    if let Err(e) = request.respond(response) {
+      if args.verbose { eprintln!("Unable to send response: {}", e) }
+    }
+  }
+  
+  Ok(())
 }
 
-fn serve(_args: ArgsForServe) {
-  fail(emg::ErrorCode::NotImplemented, "Serve not implemented yet");
+/// Builds the document printed by `inspect --format json`
+fn describe(emg_module: &mut EMGModule, wasm: &Path) -> Result<
+serde_json::Value, HostError> {
+  let size = match std::fs::metadata(wasm) {
+    Ok(metadata) => metadata.len(),
+    Err(e) => return Err(HostError::IO { path: Some(wasm.to_path_buf()),
+      message: format!("Unable to read {}: {}", wasm.display(), e),
+      source: Some(e) }),
+  };
+  
+  let (minimum_pages, maximum_pages) = emg_module.memory_pages();
+  
+  Ok(serde_json::json!({
+    "module": wasm,
+    "size": size,
+    "generators": emg_module.generators(),
+    "accessors": emg::host::POINTER_ACCESSORS,
+    "memory": {
+      "minimum_pages": minimum_pages,
+      "maximum_pages": maximum_pages,
+    },
+  }))
 }
 
-fn inspect(args: ArgsForInspect) {
-  let mut emg_module = EMGModule::from_file(args.wasm);
+fn inspect(args: ArgsForInspect) -> Result<(), Failure> {
+  let mut emg_module = load_module(&args.wasm, &args.cache)?;
+  
+  if let InspectFormat::Json = args.format {
+    let description = describe(&mut emg_module, &args.wasm)?;
+    // Can .unwrap() because a json! value is always serializable
+    println!("{}", serde_json::to_string_pretty(&description).unwrap());
+    return Ok(());
+  }
   
   eprintln!("Model generators found:");
   
-  for name in emg_module.generator_names {
-    // Can .unwrap() because these functions were already validated
-    let generator = emg_module.instance.get_func(&mut emg_module.store,
-      name.as_str()).unwrap();
+  for generator in emg_module.generators() {
+    eprintln!("\t{}", generator.signature());
     
-    let mut parameter_strings = Vec::new();
-    
-    for parameter in generator.ty(&emg_module.store).params() {
-      let mut string = String::new();
-      // .unwrap() because I don't understand how writing a string could fail
-      write!(string, "{}", parameter).unwrap();
-      parameter_strings.push(string);
+    for line in generator.doc.lines() {
+      eprintln!("\t\t{}", line);
     }
-    
-    eprintln!("\t{} ( {} )", &name[4..], parameter_strings.join(", "))
   }
+  
+  Ok(())
+}
+
+fn precompile(args: ArgsForPrecompile) -> Result<(), Failure> {
+  let wasm = match std::fs::read(&args.wasm) {
+    Ok(b) => b,
+    Err(e) => return Err(HostError::IO { path: Some(args.wasm.clone()),
+      message: format!("Unable to read {}: {}", args.wasm.display(), e),
+      source: Some(e) }.into()),
+  };
+  
+  let cwasm = emg::host::precompile(&wasm, &(&args.cache).into())?;
+  
+  let output = args.output.clone()
+    .unwrap_or_else(|| args.wasm.with_extension("cwasm"));
+  write_atomically(&output, &cwasm)?;
+  
+  eprintln!("Wrote {}", output.display());
+  
+  Ok(())
 }
 
 fn main() {
   let args = Cli::parse();
   
-  match args.subcommand {
+  let result = match args.subcommand {
     Subcommands::Gen(args) => gen(args),
+    Subcommands::Batch(args) => batch(args),
+    Subcommands::Build(args) => build(args),
     Subcommands::Serve(args) => serve(args),
     Subcommands::Inspect(args) => inspect(args),
+    Subcommands::Precompile(args) => precompile(args),
   };
+  
+  // Serve reports errors to the client and keeps running, but any other error
+  // stops the subcommand and ends up here
+  if let Err(failure) = result {
+    eprintln!("Error: {}", failure);
+    std::process::exit(failure.code());
+  }
 }
@@ -2,21 +2,73 @@ use std::sync::Mutex;
 use std::sync::atomic::{Ordering, AtomicU32};
 
 pub use nalgebra::Vector3 as V3;
+pub use nalgebra::{Matrix4, UnitQuaternion};
 
 pub mod prelude {
+  /// Exports a function as a model generator. Parameters may be `i32`, `i64`,
+  /// `f32`, `f64`, `bool`, `Color3`, `&str`, `String`, or an enum deriving
+  /// `EnumParameter`, and may have a default. Number parameters may also have
+  /// a range:
+  ///
+  /// ```
+  /// use emg::prelude::*;
+  ///
+  /// #[emg]
+  /// fn wall(
+  ///   #[emg(default = 2, range = 1..=10)]
+  ///   height: i32,
+  /// ) -> Result<GLTF, ErrorCode> {
+  ///   Ok(GLTF::new("Wall"))
+  /// }
+  /// ```
+  ///
+  /// A default outside its range is a compile error:
+  ///
+  /// ```compile_fail
+  /// use emg::prelude::*;
+  ///
+  /// #[emg]
+  /// fn wall(
+  ///   #[emg(default = 20, range = 1..=10)]
+  ///   height: i32,
+  /// ) -> Result<GLTF, ErrorCode> {
+  ///   Ok(GLTF::new("Wall"))
+  /// }
+  /// ```
+  ///
+  /// So is any other parameter type:
+  ///
+  /// ```compile_fail
+  /// use emg::prelude::*;
+  ///
+  /// #[emg]
+  /// fn wall(height: u8) -> Result<GLTF, ErrorCode> {
+  ///   Ok(GLTF::new("Wall"))
+  /// }
+  /// ```
   pub use emg_macros::emg;
+  pub use emg_macros::EnumParameter;
+  pub use crate::EnumParameter;
+  pub use crate::Color3;
   pub use crate::Geometry;
   pub use crate::GLTF;
   pub use crate::Scene;
   pub use crate::Node;
   pub use crate::ErrorCode;
+  pub use crate::Error;
   
   pub use nalgebra::Vector3 as V3;
+  pub use nalgebra::{Matrix4, UnitQuaternion};
 }
 
+#[cfg(feature = "host")]
+pub mod host;
+
 pub static MUTEX_TEST: Mutex<Vec<u8>> = Mutex::new(Vec::new());
 static MODEL_POINTER: AtomicU32 = AtomicU32::new(0);
 static MODEL_SIZE: AtomicU32 = AtomicU32::new(0);
+static ERROR_POINTER: AtomicU32 = AtomicU32::new(0);
+static ERROR_SIZE: AtomicU32 = AtomicU32::new(0);
 
 // WebAssembly is rumored to always be 32 bit, so assume that's the pointer size
 #[no_mangle]
@@ -30,6 +82,32 @@ pub extern "C" fn model_size() -> i32 {
   MODEL_SIZE.load(Ordering::Relaxed) as i32
 }
 
+// WebAssembly is rumored to always be 32 bit, so assume that's the pointer size
+#[no_mangle]
+pub extern "C" fn error_pointer() -> i32 {
+  ERROR_POINTER.load(Ordering::Relaxed) as i32
+}
+
+// WebAssembly is rumored to always be 32 bit, so assume that's the pointer size
+#[no_mangle]
+pub extern "C" fn error_size() -> i32 {
+  ERROR_SIZE.load(Ordering::Relaxed) as i32
+}
+
+/// Allocates WebAssembly memory for the host to copy string parameters into.
+/// The #[emg] macro takes ownership of the memory when the model generator is
+/// called, so it is freed afterward
+#[no_mangle]
+pub extern "C" fn emg_alloc(size: i32) -> i32 {
+  // Vec::with_capacity() allocates exactly the requested capacity, which the
+  // #[emg] macro relies on when it rebuilds the Vec
+  let mut buffer = Vec::<u8>::with_capacity(size as usize);
+  let pointer = buffer.as_mut_ptr();
+  std::mem::forget(buffer);
+  
+  pointer as i32
+}
+
 // These error codes are returned from WebAssembly functions, so must use a
 // WebAssembly variable type
 #[derive(Debug, Copy, Clone)]
@@ -48,6 +126,120 @@ pub enum ErrorCode {
   ParameterType = 10,
   ParameterOutOfRange = 11,
   OutputNotGLB = 12,
+  IO = 13,
+  BuildFile = 14,
+}
+
+impl TryFrom<i32> for ErrorCode {
+  type Error = i32;
+  
+  fn try_from(code: i32) -> Result<Self, i32> {
+    match code {
+      0 => Ok(Self::None),
+      1 => Ok(Self::Mutex),
+      2 => Ok(Self::Generation),
+      3 => Ok(Self::NotImplemented),
+      4 => Ok(Self::WebAssemblyCompile),
+      5 => Ok(Self::WebAssemblyInstance),
+      6 => Ok(Self::WebAssemblyExecution),
+      7 => Ok(Self::ModuleNotEMG),
+      8 => Ok(Self::ModelGeneratorNotFound),
+      9 => Ok(Self::ParameterCount),
+      10 => Ok(Self::ParameterType),
+      11 => Ok(Self::ParameterOutOfRange),
+      12 => Ok(Self::OutputNotGLB),
+      13 => Ok(Self::IO),
+      14 => Ok(Self::BuildFile),
+      code => Err(code),
+    }
+  }
+}
+
+/// Error returned by a model generator, with a message to show the user. Model
+/// generators may return either this or a plain ErrorCode
+#[derive(Debug, Clone)]
+pub struct Error {
+  pub code: ErrorCode,
+  pub message: String,
+}
+
+impl Error {
+  pub fn new<S: Into<String>>(code: ErrorCode, message: S) -> Self {
+    Self { code, message: message.into() }
+  }
+}
+
+impl From<ErrorCode> for Error {
+  fn from(code: ErrorCode) -> Self {
+    Self { code, message: String::new() }
+  }
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(i32)]
+pub enum LogLevel {
+  Error = 1,
+  Warn = 2,
+  Info = 3,
+  Debug = 4,
+}
+
+impl TryFrom<i32> for LogLevel {
+  type Error = i32;
+  
+  fn try_from(level: i32) -> Result<Self, i32> {
+    match level {
+      1 => Ok(Self::Error),
+      2 => Ok(Self::Warn),
+      3 => Ok(Self::Info),
+      4 => Ok(Self::Debug),
+      level => Err(level),
+    }
+  }
+}
+
+// Provided by the host. Only imported if something logs, so hosts without
+// logging can still run model generators that don't use it
+#[cfg(target_family = "wasm")]
+#[link(wasm_import_module = "emg")]
+extern "C" {
+  fn emg_log(level: i32, pointer: *const u8, length: usize);
+}
+
+/// Sends a message to the host, which may show it to the user. Usually called
+/// through emg::log!() or emg::debug!(). Outside of WebAssembly (such as in
+/// tests), messages go to stderr
+pub fn log_message(level: LogLevel, message: &str) {
+  #[cfg(target_family = "wasm")]
+  unsafe { emg_log(level as i32, message.as_ptr(), message.len()) }
+  
+  #[cfg(not(target_family = "wasm"))]
+  eprintln!("[{:?}] {}", level, message);
+}
+
+/// Logs a message to the host, with format!() syntax
+#[macro_export]
+macro_rules! log {
+  ($($arg:tt)*) => {
+    $crate::log_message($crate::LogLevel::Info, &format!($($arg)*))
+  };
+}
+
+/// Logs a debugging message to the host, with format!() syntax
+#[macro_export]
+macro_rules! debug {
+  ($($arg:tt)*) => {
+    $crate::log_message($crate::LogLevel::Debug, &format!($($arg)*))
+  };
+}
+
+/// Enums used as model generator parameters. Derive with
+/// `#[derive(EnumParameter)]`, which also records the variant names in the
+/// module's emg metadata. Passed through WebAssembly as the variant index
+pub trait EnumParameter: Sized {
+  const VARIANTS: &'static [&'static str];
+  
+  fn from_index(index: i32) -> Option<Self>;
 }
 
 struct DryRunWriter {
@@ -71,11 +263,14 @@ impl std::io::Write for DryRunWriter {
   }
 }
 
+#[derive(Default)]
 pub enum SelectionType {
+  #[default]
   VERTICES,
   TRIANGLES,
 }
 
+#[derive(Default)]
 pub struct Geometry {
   pub vertices: Vec<V3<f64>>,
   
@@ -153,11 +348,216 @@ impl Geometry {
     self
   }
   
-  // rotations / matrices
+  /// Apply a rotation from Euler angles, in radians. Rotates around X first,
+  /// then Y, then Z
+  pub fn r(&mut self, x: f64, y: f64, z: f64) -> &mut Self {
+    self.r_quaternion(&UnitQuaternion::from_euler_angles(x, y, z))
+  }
+  
+  /// Apply a rotation of `angle` radians around `axis`, which does not need to
+  /// be normalized
+  pub fn r_axis(&mut self, axis: V3<f64>, angle: f64) -> &mut Self {
+    self.r_quaternion(&Self::axis_rotation(axis, angle))
+  }
+  
+  fn axis_rotation(axis: V3<f64>, angle: f64) -> UnitQuaternion<f64> {
+    match nalgebra::Unit::try_new(axis, 1e-12) {
+      Some(axis) => UnitQuaternion::from_axis_angle(&axis, angle),
+      // A zero axis has no direction to rotate around
+      None => UnitQuaternion::identity(),
+    }
+  }
+  
+  /// Apply a rotation given as a quaternion
+  pub fn r_quaternion(&mut self, rotation: &UnitQuaternion<f64>) -> &mut Self {
+    for vertex in &mut self.vertices {
+      *vertex = rotation * *vertex;
+    }
+    
+    self
+  }
+  
+  /// Apply a rotation from Euler angles (same as .r()) around `pivot` instead
+  /// of the origin
+  pub fn r_pivot(&mut self, pivot: V3<f64>, x: f64, y: f64, z: f64) ->
+  &mut Self {
+    self.t(-pivot.x, -pivot.y, -pivot.z)
+      .r(x, y, z)
+      .t(pivot.x, pivot.y, pivot.z)
+  }
+  
+  /// Apply a scale around `pivot` instead of the origin
+  pub fn s_pivot(&mut self, pivot: V3<f64>, x: f64, y: f64, z: f64) ->
+  &mut Self {
+    self.t(-pivot.x, -pivot.y, -pivot.z)
+      .s(x, y, z)
+      .t(pivot.x, pivot.y, pivot.z)
+  }
+  
+  /// Apply a 4x4 homogeneous transformation matrix. As with negative scales,
+  /// matrices that mirror the geometry also reverse its triangle winding
+  pub fn transform(&mut self, matrix: &Matrix4<f64>) -> &mut Self {
+    for vertex in &mut self.vertices {
+      *vertex = matrix.transform_point(&(*vertex).into()).coords;
+    }
+    
+    self
+  }
+  
+  /// Indices of the vertices covered by the selection. For a triangle
+  /// selection, these are the corners of the selected triangles
+  fn selected_vertices(&self) -> Vec<u32> {
+    let mut vertices: Vec<u32> = match self.selection_type {
+      SelectionType::VERTICES => self.selection.clone(),
+      SelectionType::TRIANGLES => self.selection.iter()
+        .flat_map(|triangle| self.triangles[*triangle as usize]).collect(),
+    };
+    
+    // Selected triangles usually share vertices, which must only be moved once
+    vertices.sort_unstable();
+    vertices.dedup();
+    
+    vertices
+  }
+  
+  /// Same as .transform(), but only moves the selected vertices, or the
+  /// vertices of the selected triangles
+  pub fn transform_selected(&mut self, matrix: &Matrix4<f64>) -> &mut Self {
+    for i in self.selected_vertices() {
+      let vertex = &mut self.vertices[i as usize];
+      *vertex = matrix.transform_point(&(*vertex).into()).coords;
+    }
+    
+    self
+  }
+  
+  /// Same as .t(), but only for the selection
+  pub fn t_selected(&mut self, x: f64, y: f64, z: f64) -> &mut Self {
+    self.transform_selected(&Matrix4::new_translation(&V3::new(x, y, z)))
+  }
+  
+  /// Same as .s(), but only for the selection
+  pub fn s_selected(&mut self, x: f64, y: f64, z: f64) -> &mut Self {
+    self.transform_selected(&Matrix4::new_nonuniform_scaling(
+      &V3::new(x, y, z)))
+  }
+  
+  /// Same as .s_pivot(), but only for the selection
+  pub fn s_pivot_selected(&mut self, pivot: V3<f64>, x: f64, y: f64, z: f64)
+  -> &mut Self {
+    self.transform_selected(&(Matrix4::new_translation(&pivot) *
+      Matrix4::new_nonuniform_scaling(&V3::new(x, y, z)) *
+      Matrix4::new_translation(&-pivot)))
+  }
+  
+  /// Same as .r(), but only for the selection
+  pub fn r_selected(&mut self, x: f64, y: f64, z: f64) -> &mut Self {
+    self.r_quaternion_selected(&UnitQuaternion::from_euler_angles(x, y, z))
+  }
+  
+  /// Same as .r_axis(), but only for the selection
+  pub fn r_axis_selected(&mut self, axis: V3<f64>, angle: f64) -> &mut Self {
+    self.r_quaternion_selected(&Self::axis_rotation(axis, angle))
+  }
+  
+  /// Same as .r_quaternion(), but only for the selection
+  pub fn r_quaternion_selected(&mut self, rotation: &UnitQuaternion<f64>) ->
+  &mut Self {
+    self.transform_selected(&rotation.to_homogeneous())
+  }
   
-  // Merges
+  /// Same as .r_pivot(), but only for the selection
+  pub fn r_pivot_selected(&mut self, pivot: V3<f64>, x: f64, y: f64, z: f64)
+  -> &mut Self {
+    self.transform_selected(&(Matrix4::new_translation(&pivot) *
+      UnitQuaternion::from_euler_angles(x, y, z).to_homogeneous() *
+      Matrix4::new_translation(&-pivot)))
+  }
+  
+  /// Appends the vertices and triangles of `other`. The selection is kept,
+  /// since existing indices do not change
+  pub fn merge(&mut self, other: &Geometry) -> &mut Self {
+    let offset = self.vertices.len() as u32;
+    
+    self.vertices.extend_from_slice(&other.vertices);
+    self.triangles.extend(other.triangles.iter().map(|triangle|
+      triangle.map(|vertex| vertex + offset)));
+    
+    self
+  }
   
-  // Vertex deduplication
+  /// Combines several parts into one Geometry, so that they can be packed as a
+  /// single MeshPrimitive
+  pub fn from_parts<I>(parts: I) -> Self
+  where I: IntoIterator, I::Item: std::borrow::Borrow<Geometry> {
+    use std::borrow::Borrow;
+    
+    let mut result = Self::default();
+    
+    for part in parts {
+      result.merge(part.borrow());
+    }
+    
+    result
+  }
+  
+  /// Merges vertices within `epsilon` of each other, keeping the first of
+  /// each group, and removes triangles that become degenerate. Clears the
+  /// selection, since vertex and triangle indices change
+  pub fn weld(&mut self, epsilon: f64) -> &mut Self {
+    // Vertices are hashed into cubic cells at least epsilon wide, so that any
+    // vertex within epsilon of another is in the same or an adjacent cell.
+    // Cells are also at least as wide as the gap between floats at the largest
+    // coordinate, which keeps cell indices (and their neighbors) within i64
+    let max_coordinate = self.vertices.iter().flat_map(|vertex| vertex.iter())
+      .fold(0.0, |max: f64, coordinate| max.max(coordinate.abs()));
+    let cell_size = epsilon.max(max_coordinate*f64::EPSILON);
+    let cell_size = if cell_size > 0.0 { cell_size } else { 1.0 };
+    let cell = |vertex: &V3<f64>| (vertex / cell_size)
+      .map(|v| v.floor() as i64);
+    
+    let mut cells: std::collections::HashMap<V3<i64>, Vec<u32>> =
+      std::collections::HashMap::new();
+    let mut welded: Vec<V3<f64>> = Vec::new();
+    let mut remap = Vec::with_capacity(self.vertices.len());
+    
+    for vertex in &self.vertices {
+      let home = cell(vertex);
+      
+      let mut existing = None;
+      'search: for x in -1..=1 {
+        for y in -1..=1 {
+          for z in -1..=1 {
+            let neighbors = match cells.get(&(home + V3::new(x, y, z))) {
+              Some(n) => n,
+              None => continue,
+            };
+            
+            existing = neighbors.iter().copied().find(|i|
+              (welded[*i as usize] - vertex).norm() <= epsilon);
+            if existing.is_some() { break 'search }
+          }
+        }
+      }
+      
+      remap.push(existing.unwrap_or_else(|| {
+        welded.push(*vertex);
+        let i = welded.len() as u32 - 1;
+        cells.entry(home).or_default().push(i);
+        i
+      }));
+    }
+    
+    self.vertices = welded;
+    for triangle in &mut self.triangles {
+      *triangle = triangle.map(|vertex| remap[vertex as usize]);
+    }
+    self.triangles.retain(|[a, b, c]| a != b && b != c && c != a);
+    
+    self.selection.drain(..);
+    
+    self
+  }
   
   /// Returns a list of vertices within the bounding box defined by the given
   /// points. Allows error of 1e-6
@@ -312,6 +712,306 @@ impl Geometry {
     }
   }
   
+  /// Flat grid on the XY plane from -1 to 1, facing +Z
+  pub fn grid(x_segments: u32, y_segments: u32) -> Self {
+    let x_segments = x_segments.max(1);
+    let y_segments = y_segments.max(1);
+    let index = |i: u32, j: u32| j*(x_segments + 1) + i;
+    
+    let mut result = Self::default();
+    
+    for j in 0..=y_segments {
+      for i in 0..=x_segments {
+        result.vertices.push(V3::new(
+          2.0*i as f64/x_segments as f64 - 1.0,
+          2.0*j as f64/y_segments as f64 - 1.0,
+          0.0,
+        ));
+      }
+    }
+    
+    for j in 0..y_segments {
+      for i in 0..x_segments {
+        result.triangles.push([index(i, j), index(i + 1, j), index(i, j + 1)]);
+        result.triangles.push([index(i + 1, j), index(i + 1, j + 1),
+          index(i, j + 1)]);
+      }
+    }
+    
+    result
+  }
+  
+  /// Single square on the XY plane from -1 to 1, facing +Z
+  pub fn plane() -> Self {
+    Self::grid(1, 1)
+  }
+  
+  /// Revolves a profile around the Z axis. Profile points are (radius, z),
+  /// ordered so that the solid is on their right when looking from +Y, which
+  /// is top to bottom for an ordinary solid. Points with a radius of zero
+  /// become a single vertex. If `closed`, the last point connects back to the
+  /// first
+  fn lathe(profile: &[(f64, f64)], segments: u32, closed: bool) -> Self {
+    let segments = segments.max(3);
+    
+    let mut result = Self::default();
+    
+    // Index of each profile point's first vertex, and whether it is a pole
+    let mut rings = Vec::new();
+    for (radius, z) in profile {
+      rings.push((result.vertices.len() as u32, *radius == 0.0));
+      
+      if *radius == 0.0 {
+        result.vertices.push(V3::new(0.0, 0.0, *z));
+        continue;
+      }
+      
+      for j in 0..segments {
+        let angle = std::f64::consts::TAU*j as f64/segments as f64;
+        result.vertices.push(V3::new(radius*angle.cos(), radius*angle.sin(),
+          *z));
+      }
+    }
+    
+    let mut pairs: Vec<(usize, usize)> = (1..rings.len()).map(|k| (k - 1, k))
+      .collect();
+    if closed {
+      pairs.push((rings.len() - 1, 0));
+    }
+    
+    for (upper, lower) in pairs {
+      let (a, a_pole) = rings[upper];
+      let (b, b_pole) = rings[lower];
+      
+      for j in 0..segments {
+        let next = (j + 1) % segments;
+        
+        match (a_pole, b_pole) {
+          (true, true) => {},
+          (true, false) => result.triangles.push([a, b + j, b + next]),
+          (false, true) => result.triangles.push([a + j, b, a + next]),
+          (false, false) => {
+            result.triangles.push([a + j, b + j, a + next]);
+            result.triangles.push([a + next, b + j, b + next]);
+          },
+        }
+      }
+    }
+    
+    result
+  }
+  
+  /// Sphere of radius 1 made of rings of latitude, with poles on the Z axis.
+  /// `segments` is the number of vertices around each ring (at least 3), and
+  /// `rings` the number of bands from pole to pole (at least 2)
+  pub fn uv_sphere(segments: u32, rings: u32) -> Self {
+    let rings = rings.max(2);
+    
+    let profile: Vec<(f64, f64)> = (0..=rings).map(|k| {
+      let angle = std::f64::consts::PI*k as f64/rings as f64;
+      // The poles are set exactly, so that lathe() recognizes them
+      if k == 0 || k == rings { (0.0, angle.cos().round()) }
+      else { (angle.sin(), angle.cos()) }
+    }).collect();
+    
+    Self::lathe(&profile, segments, false)
+  }
+  
+  /// Sphere of radius 1 made by subdividing an icosahedron. Triangles are
+  /// more even than in uv_sphere(), and each subdivision multiplies their
+  /// number by 4
+  pub fn icosphere(subdivisions: u32) -> Self {
+    let t = (1.0 + 5f64.sqrt())/2.0;
+    
+    let mut result = Self {
+      vertices: [
+        (-1.0,    t,  0.0), ( 1.0,    t,  0.0),
+        (-1.0,   -t,  0.0), ( 1.0,   -t,  0.0),
+        ( 0.0, -1.0,    t), ( 0.0,  1.0,    t),
+        ( 0.0, -1.0,   -t), ( 0.0,  1.0,   -t),
+        (   t,  0.0, -1.0), (   t,  0.0,  1.0),
+        (  -t,  0.0, -1.0), (  -t,  0.0,  1.0),
+      ].iter().map(|(x, y, z)| V3::new(*x, *y, *z).normalize()).collect(),
+      triangles: vec![
+        [0, 11,  5], [0,  5,  1], [ 0,  1,  7], [ 0,  7, 10], [0, 10, 11],
+        [1,  5,  9], [5, 11,  4], [11, 10,  2], [10,  7,  6], [7,  1,  8],
+        [3,  9,  4], [3,  4,  2], [ 3,  2,  6], [ 3,  6,  8], [3,  8,  9],
+        [4,  9,  5], [2,  4, 11], [ 6,  2, 10], [ 8,  6,  7], [9,  8,  1],
+      ],
+      ..Default::default()
+    };
+    
+    for _ in 0..subdivisions {
+      // Each edge is shared by two triangles, which must use the same midpoint
+      let mut midpoints = std::collections::HashMap::new();
+      let mut midpoint = |a: u32, b: u32, vertices: &mut Vec<V3<f64>>| {
+        *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+          vertices.push((vertices[a as usize] + vertices[b as usize])
+            .normalize());
+          vertices.len() as u32 - 1
+        })
+      };
+      
+      let mut triangles = Vec::with_capacity(result.triangles.len()*4);
+      for [a, b, c] in &result.triangles {
+        let ab = midpoint(*a, *b, &mut result.vertices);
+        let bc = midpoint(*b, *c, &mut result.vertices);
+        let ca = midpoint(*c, *a, &mut result.vertices);
+        
+        triangles.extend([[*a, ab, ca], [*b, bc, ab], [*c, ca, bc],
+          [ab, bc, ca]]);
+      }
+      result.triangles = triangles;
+    }
+    
+    result
+  }
+  
+  /// Capped cylinder of radius 1 from z = -1 to 1. `segments` is the number of
+  /// sides (at least 3)
+  pub fn cylinder(segments: u32) -> Self {
+    Self::lathe(&[(0.0, 1.0), (1.0, 1.0), (1.0, -1.0), (0.0, -1.0)],
+      segments, false)
+  }
+  
+  /// Cone with a base of radius 1 at z = -1 and its tip at z = 1. `segments`
+  /// is the number of sides (at least 3)
+  pub fn cone(segments: u32) -> Self {
+    Self::lathe(&[(0.0, 1.0), (1.0, -1.0), (0.0, -1.0)], segments, false)
+  }
+  
+  /// Torus around the Z axis, with a distance of 1 from its center to the
+  /// middle of its tube. `major_segments` is the number of steps around the
+  /// Z axis, and `minor_segments` around the tube (at least 3 each)
+  pub fn torus(major_segments: u32, minor_segments: u32, minor_radius: f64) ->
+  Self {
+    let minor_segments = minor_segments.max(3);
+    
+    // Starts at the top of the tube and goes around the outside first
+    let profile: Vec<(f64, f64)> = (0..minor_segments).map(|k| {
+      let angle = std::f64::consts::TAU*k as f64/minor_segments as f64;
+      (1.0 + minor_radius*angle.sin(), minor_radius*angle.cos())
+    }).collect();
+    
+    Self::lathe(&profile, major_segments, true)
+  }
+  
+  /// Cylinder of radius 1 with hemispherical ends, along the Z axis. `length`
+  /// is the length of the straight part, so the capsule spans
+  /// z = -1 - length/2 to 1 + length/2. `segments` is the number of vertices
+  /// around each ring (at least 3), and `rings` the number of bands in each
+  /// hemisphere (at least 1)
+  pub fn capsule(segments: u32, rings: u32, length: f64) -> Self {
+    let rings = rings.max(1);
+    let half = length/2.0;
+    
+    let mut profile = vec![(0.0, 1.0 + half)];
+    for k in 1..=rings {
+      let angle = std::f64::consts::FRAC_PI_2*k as f64/rings as f64;
+      profile.push((angle.sin(), half + angle.cos()));
+    }
+    // Without a straight part, both hemispheres share the same equator
+    let first = if length > 0.0 { 0 } else { 1 };
+    for k in first..rings {
+      let angle = std::f64::consts::FRAC_PI_2*k as f64/rings as f64;
+      profile.push((angle.cos(), -half - angle.sin()));
+    }
+    profile.push((0.0, -1.0 - half));
+    
+    Self::lathe(&profile, segments, false)
+  }
+  
+  /// Ramp filling the lower half of a cube from -1 to 1. The bottom is at
+  /// z = -1, the tall end is at x = -1, and the slope faces +X and +Z
+  pub fn wedge() -> Self {
+    Self {
+      vertices: vec![
+        V3::new(-1.0, -1.0, -1.0),
+        V3::new(-1.0,  1.0, -1.0),
+        V3::new( 1.0, -1.0, -1.0),
+        V3::new( 1.0,  1.0, -1.0),
+        
+        V3::new(-1.0, -1.0,  1.0),
+        V3::new(-1.0,  1.0,  1.0),
+      ],
+      triangles: vec![
+        // Bottom
+        [0, 1, 2],
+        [1, 3, 2],
+        
+        // -X side
+        [0, 4, 1],
+        [1, 4, 5],
+        
+        // Slope
+        [2, 3, 4],
+        [3, 5, 4],
+        
+        // -Y side
+        [0, 2, 4],
+        
+        // +Y side
+        [1, 5, 3],
+      ],
+      ..Default::default()
+    }
+  }
+  
+  /// Half ring standing on the XY plane, with an outer radius of 1 and its
+  /// opening facing -Z. It spans x = -1 to 1, z = 0 to 1, and y = -1 to 1.
+  /// `segments` is the number of steps along the curve (at least 1), and
+  /// `inner_radius` should be between 0 and 1
+  pub fn arch(segments: u32, inner_radius: f64) -> Self {
+    let segments = segments.max(1);
+    // Vertices for each step are outer front, outer back, inner front, inner
+    // back, where the front is at y = -1
+    let index = |k: u32, outer: bool, back: bool| 4*k + 2*(!outer as u32) +
+      back as u32;
+    
+    let mut result = Self::default();
+    
+    for k in 0..=segments {
+      let angle = std::f64::consts::PI*k as f64/segments as f64;
+      let (x, z) = (angle.cos(), angle.sin());
+      
+      for radius in [1.0, inner_radius] {
+        for y in [-1.0, 1.0] {
+          result.vertices.push(V3::new(radius*x, y, radius*z));
+        }
+      }
+    }
+    
+    for k in 0..segments {
+      let (o, o_b) = (index(k, true, false), index(k, true, true));
+      let (i, i_b) = (index(k, false, false), index(k, false, true));
+      let (o2, o2_b) = (index(k + 1, true, false), index(k + 1, true, true));
+      let (i2, i2_b) = (index(k + 1, false, false), index(k + 1, false, true));
+      
+      result.triangles.extend([
+        // Outer curve
+        [o, o_b, o2], [o2, o_b, o2_b],
+        // Inner curve
+        [i, i2, i_b], [i2, i2_b, i_b],
+        // Front
+        [o, o2, i], [o2, i2, i],
+        // Back
+        [o_b, i_b, o2_b], [o2_b, i_b, i2_b],
+      ]);
+    }
+    
+    // Ends the arch stands on
+    let (o, o_b) = (index(0, true, false), index(0, true, true));
+    let (i, i_b) = (index(0, false, false), index(0, false, true));
+    result.triangles.extend([[o, i, o_b], [i, i_b, o_b]]);
+    
+    let (o, o_b) = (index(segments, true, false), index(segments, true, true));
+    let (i, i_b) = (index(segments, false, false),
+      index(segments, false, true));
+    result.triangles.extend([[o, o_b, i], [i, o_b, i_b]]);
+    
+    result
+  }
+  
   // Use self instead of &self to cause a move, because this struct should not
   // be used again after packing
   pub fn pack(self, gltf: &mut GLTF) -> MeshPrimitive {
@@ -712,6 +1412,25 @@ impl Color4 {
   pub fn is_default(&self) -> bool { *self == Self::new() }
 }
 
+/// RGB color for model generator parameters. Passed through WebAssembly as a
+/// 0xRRGGBB integer
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Color3 {
+  pub r: f64,
+  pub g: f64,
+  pub b: f64,
+}
+
+impl Color3 {
+  pub fn from_hex(hex: i32) -> Self {
+    Self {
+      r: ((hex >> 16) & 0xff) as f64 / 255.0,
+      g: ((hex >>  8) & 0xff) as f64 / 255.0,
+      b: ((hex      ) & 0xff) as f64 / 255.0,
+    }
+  }
+}
+
 #[derive(Copy, Clone, serde::Serialize)]
 pub struct PBRMetallicRoughness {
   #[serde(rename = "baseColorFactor")]
@@ -819,8 +1538,8 @@ impl Material {
   pub fn rgba(&mut self, red: f64, green: f64, blue: f64, alpha: f64) -> &mut
   Self {
     self.pbr_metallic_roughness.base_color_factor.r = red;
-    self.pbr_metallic_roughness.base_color_factor.g = blue;
-    self.pbr_metallic_roughness.base_color_factor.b = green;
+    self.pbr_metallic_roughness.base_color_factor.g = green;
+    self.pbr_metallic_roughness.base_color_factor.b = blue;
     self.pbr_metallic_roughness.base_color_factor.a = alpha;
     self
   }
@@ -828,8 +1547,8 @@ impl Material {
   // Set RGB color
   pub fn rgb(&mut self, red: f64, green: f64, blue: f64) -> &mut Self {
     self.pbr_metallic_roughness.base_color_factor.r = red;
-    self.pbr_metallic_roughness.base_color_factor.g = blue;
-    self.pbr_metallic_roughness.base_color_factor.b = green;
+    self.pbr_metallic_roughness.base_color_factor.g = green;
+    self.pbr_metallic_roughness.base_color_factor.b = blue;
     self
   }
   
@@ -1255,3 +1974,18 @@ pub fn write_gltf(buffer: &mut Vec<u8>, gltf: GLTF) {
   MODEL_POINTER.store(buffer.as_ptr() as u32, Ordering::Relaxed);
   MODEL_SIZE.store(buffer.len() as u32, Ordering::Relaxed);
 }
+
+/// Writes a model generator's error message into the buffer, where the host
+/// can find it through error_pointer() and error_size()
+pub fn write_error(buffer: &mut Vec<u8>, error: &Error) {
+  *buffer = error.message.as_bytes().to_vec();
+  
+  ERROR_POINTER.store(buffer.as_ptr() as u32, Ordering::Relaxed);
+  ERROR_SIZE.store(buffer.len() as u32, Ordering::Relaxed);
+}
+
+/// Clears the message from any previous error, so that errors without messages
+/// are not reported with a stale one
+pub fn clear_error() {
+  ERROR_SIZE.store(0, Ordering::Relaxed);
+}
@@ -0,0 +1,1671 @@
+//! Loads emg modules and runs their model generators, for programs that embed
+//! emg instead of running the emg command line tool. Requires the `host`
+//! feature
+//!
+//! ```no_run
+//! use emg::host::{CacheOptions, EMGModule, Limits};
+//!
+//! let mut emg_module = EMGModule::from_file("blocks.wasm".as_ref(),
+//!   &CacheOptions::default())?;
+//! let glb = emg_module.generate("block", &[], &[String::from("name=Sign")],
+//!   &Limits::default())?;
+//! # Ok::<(), emg::host::HostError>(())
+//! ```
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+
+use crate::ErrorCode;
+
+/// An error from loading an emg module or running a model generator. Each
+/// variant carries what is known about where the error happened
+#[derive(Debug)]
+pub enum HostError {
+  /// A file could not be read or written, or a server could not be started
+  IO {
+    path: Option<PathBuf>,
+    message: String,
+    source: Option<std::io::Error>,
+  },
+  
+  /// The module could not be compiled or instantiated, or does not follow
+  /// emg's conventions. `code` tells which
+  Module {
+    code: ErrorCode,
+    message: String,
+    source: Option<wasmtime::Error>,
+  },
+  
+  /// The module has no model generator with this name
+  GeneratorNotFound {
+    generator: String,
+    message: String,
+  },
+  
+  /// Parameters could not be passed to a model generator. `index` is the
+  /// position of the parameter at fault, if there is one
+  Parameter {
+    code: ErrorCode,
+    generator: Option<String>,
+    index: Option<usize>,
+    message: String,
+  },
+  
+  /// The model generator returned a nonzero code, which is passed through
+  /// as-is even if it is not an ErrorCode. `message` is what the model
+  /// generator wrote along with it, if anything
+  Generation {
+    generator: String,
+    code: i32,
+    message: Option<String>,
+  },
+  
+  /// The model generator trapped, or was stopped for exceeding a limit
+  Execution {
+    generator: String,
+    message: String,
+    source: Option<wasmtime::Error>,
+  },
+  
+  /// The generated output is not a usable .glb. `chunk_type` is the type of
+  /// the .glb chunk at fault, if there is one
+  Output {
+    code: ErrorCode,
+    chunk_type: Option<String>,
+    message: String,
+    source: Option<serde_json::Error>,
+  },
+}
+
+impl HostError {
+  fn module<S: Into<String>>(code: ErrorCode, message: S) -> Self {
+    Self::Module { code, message: message.into(), source: None }
+  }
+  
+  fn parameter<S: Into<String>>(code: ErrorCode, index: Option<usize>,
+  message: S) -> Self {
+    Self::Parameter { code, generator: None, index, message: message.into() }
+  }
+  
+  fn output<S: Into<String>>(code: ErrorCode, chunk_type: Option<&str>,
+  message: S) -> Self {
+    Self::Output { code, chunk_type: chunk_type.map(String::from),
+      message: message.into(), source: None }
+  }
+  
+  /// Fills in the model generator for parameter errors found before it was
+  /// known
+  fn in_generator(self, name: &str) -> Self {
+    match self {
+      Self::Parameter { code, generator: None, index, message } =>
+        Self::Parameter { code, generator: Some(String::from(name)), index,
+        message },
+      other => other,
+    }
+  }
+  
+  /// Exit code for the emg command line tool. Usually an ErrorCode, but model
+  /// generators may return any integer
+  pub fn code(&self) -> i32 {
+    match self {
+      Self::IO { .. } => ErrorCode::IO as i32,
+      Self::Module { code, .. } => *code as i32,
+      Self::GeneratorNotFound { .. } => ErrorCode::ModelGeneratorNotFound as
+        i32,
+      Self::Parameter { code, .. } => *code as i32,
+      Self::Generation { code, .. } => *code,
+      Self::Execution { .. } => ErrorCode::WebAssemblyExecution as i32,
+      Self::Output { code, .. } => *code as i32,
+    }
+  }
+  
+  /// Model generator the error happened in, if known
+  pub fn generator(&self) -> Option<&str> {
+    match self {
+      Self::GeneratorNotFound { generator, .. } |
+      Self::Generation { generator, .. } |
+      Self::Execution { generator, .. } => Some(generator),
+      Self::Parameter { generator, .. } => generator.as_deref(),
+      _ => None,
+    }
+  }
+}
+
+impl std::fmt::Display for HostError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::IO { message, .. } |
+      Self::Module { message, .. } |
+      Self::GeneratorNotFound { message, .. } |
+      Self::Parameter { message, .. } |
+      Self::Execution { message, .. } |
+      Self::Output { message, .. } => write!(f, "{}", message),
+      Self::Generation { code, message, .. } => {
+        let name = match ErrorCode::try_from(*code) {
+          Ok(code) => format!("{:?}", code),
+          Err(_) => String::from("not an emg error code"),
+        };
+        
+        write!(f, "model generation returned error code: {} ({})", code,
+          name)?;
+        match message {
+          Some(message) => write!(f, ": {}", message),
+          None => Ok(()),
+        }
+      },
+    }
+  }
+}
+
+impl std::error::Error for HostError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Self::IO { source: Some(e), .. } => Some(e),
+      Self::Module { source: Some(e), .. } |
+      Self::Execution { source: Some(e), .. } => Some(e.as_ref()),
+      Self::Output { source: Some(e), .. } => Some(e),
+      _ => None,
+    }
+  }
+}
+
+/// Formats format_output() can convert generated models to
+#[derive(Clone, Default, Debug)]
+pub enum Format {
+    /// GLTF binary format (.glb)
+    #[default]
+    GLB,
+    
+    /// GLTF text format (.gltf)
+    GLTF,
+    
+    /// GLTF text format (.gltf), with the BIN chunk in a .bin file next to it
+    /// instead of embedded as base64. Only for output to a file
+    GLTFSeparate,
+    
+    /// Pretty-printed GLTF text format (for debugging, may omit buffers
+    /// required for rendering)
+    Pretty,
+}
+
+/// Bounds on a model generator call, so that a buggy generator cannot run
+/// forever
+#[derive(Clone, Default, Debug)]
+pub struct Limits {
+  /// Longest the model generator may run, in milliseconds
+  pub timeout: Option<u64>,
+  
+  /// Fuel the model generator may consume. WebAssembly instructions consume
+  /// about one unit of fuel each
+  pub fuel: Option<u64>,
+  
+  /// Largest WebAssembly memory may grow to, in bytes
+  pub max_memory: Option<usize>,
+}
+
+/// Where compiled modules and generated output are cached, so that unchanged
+/// modules are not recompiled and unchanged models are not regenerated
+#[derive(Clone, Default, Debug)]
+pub struct CacheOptions {
+  /// Neither read nor update the cache
+  pub no_cache: bool,
+  
+  /// Cache directory. Defaults to emg/ in the user's cache directory
+  pub cache_dir: Option<PathBuf>,
+}
+
+impl CacheOptions {
+  /// None if neither `cache_dir` nor a user cache directory is set. A shared
+  /// directory such as /tmp is never used, since other users could plant
+  /// compiled modules there for emg to run
+  pub fn directory(&self) -> Option<PathBuf> {
+    if let Some(directory) = &self.cache_dir {
+      return Some(directory.clone());
+    }
+    
+    // Follows the XDG base directory spec, which also works on Mac
+    let user_cache = std::env::var_os("XDG_CACHE_HOME").map(PathBuf::from)
+      .or_else(|| std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join(".cache")))?;
+    
+    Some(user_cache.join("emg"))
+  }
+}
+
+/// Largest each directory in the cache may grow to, in bytes
+pub const MAX_CACHE_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Deletes the least recently used files in a cache directory until it is no
+/// larger than `max_size`. Errors are ignored, since the cache is only an
+/// optimization
+pub fn evict_cache(directory: &Path, max_size: u64) {
+  let entries = match std::fs::read_dir(directory) {
+    Ok(entries) => entries,
+    Err(_) => return,
+  };
+  
+  let mut files: Vec<(std::time::SystemTime, u64, PathBuf)> = entries
+    .filter_map(|entry| {
+      let entry = entry.ok()?;
+      let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+      Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+    }).collect();
+  files.sort();
+  
+  let mut size: u64 = files.iter().map(|(_, len, _)| len).sum();
+  for (_, len, path) in files {
+    if size <= max_size {
+      break;
+    }
+    if std::fs::remove_file(path).is_ok() {
+      size -= len;
+    }
+  }
+}
+
+/// Marks a cache entry as used, so that evict_cache() deletes it last
+pub fn touch_cache_entry(path: &Path) {
+  let _ = std::fs::File::options().write(true).open(path)
+    .and_then(|file| file.set_modified(std::time::SystemTime::now()));
+}
+
+/// Functions every emg module must export for retrieving generated models
+pub const POINTER_ACCESSORS: [&str; 2] = ["model_pointer", "model_size"];
+
+/// Description of a model generator, written into the `emg` custom section of
+/// the .wasm file by the #[emg] macro
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct GeneratorMetadata {
+  /// Name without the `gen_` prefix
+  pub name: String,
+  
+  #[serde(default)]
+  pub doc: String,
+  
+  pub parameters: Vec<ParameterMetadata>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ParameterMetadata {
+  // Only None for .wasm files built without emg metadata
+  pub name: Option<String>,
+  
+  /// WebAssembly type name, such as `i32`, or one of `bool`, `color`, or
+  /// `enum`, which are passed as `i32`, or `string`, which is passed as an
+  /// `i32` pointer and `i32` length
+  #[serde(rename = "type")]
+  pub type_: String,
+  
+  #[serde(default)]
+  pub doc: String,
+  
+  /// Name of the enum, for enum parameters
+  #[serde(rename = "enum", default, skip_serializing_if = "Option::is_none")]
+  pub enum_: Option<String>,
+  
+  /// Variant names, for enum parameters. Filled in from the enum's own line of
+  /// metadata
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub variants: Vec<String>,
+  
+  /// Used when the parameter is omitted. Written the same way as values given
+  /// on the command line, except that numbers and bools are not strings
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub default: Option<serde_json::Value>,
+  
+  /// Inclusive bounds
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub min: Option<serde_json::Number>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub max: Option<serde_json::Number>,
+}
+
+/// Description of an enum used by model generator parameters, written into the
+/// `emg` custom section by #[derive(EnumParameter)]
+#[derive(serde::Deserialize, Clone, Debug)]
+struct EnumMetadata {
+  #[serde(rename = "enum")]
+  name: String,
+  
+  variants: Vec<String>,
+}
+
+/// One line of the `emg` custom section
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum MetadataLine {
+  Generator(GeneratorMetadata),
+  Enum(EnumMetadata),
+}
+
+impl ParameterMetadata {
+  /// Type as shown to users. Enums are shown by name
+  pub fn type_name(&self) -> &str {
+    self.enum_.as_deref().unwrap_or(&self.type_)
+  }
+  
+  /// Types the parameter is passed as in WebAssembly
+  pub fn wasm_types(&self) -> Vec<&str> {
+    match self.type_.as_str() {
+      "bool" | "color" | "enum" => vec!["i32"],
+      "string" => vec!["i32", "i32"],
+      type_ => vec![type_],
+    }
+  }
+  
+  /// Default value as it would be given on the command line
+  pub fn default_text(&self) -> Option<String> {
+    match &self.default {
+      Some(serde_json::Value::String(text)) => Some(text.clone()),
+      Some(value) => Some(value.to_string()),
+      None => None,
+    }
+  }
+  
+  /// Default value for summaries. Strings are quoted, so that empty strings
+  /// and spaces are visible
+  pub fn default_summary(&self) -> Option<String> {
+    match (self.type_.as_str(), &self.default) {
+      ("string", Some(default)) => Some(default.to_string()),
+      _ => self.default_text(),
+    }
+  }
+  
+  /// Value used for omitted parameters that have no default
+  pub fn zero_value(&self) -> String {
+    match self.type_.as_str() {
+      "bool" => String::from("false"),
+      "color" => String::from("#000000"),
+      "enum" => self.variants.first().cloned().unwrap_or_default(),
+      "string" => String::new(),
+      _ => String::from("0"),
+    }
+  }
+  
+  /// Description of the allowed range, such as `between 0 and 10`
+  pub fn range(&self) -> Option<String> {
+    match (&self.min, &self.max) {
+      (Some(min), Some(max)) => Some(format!("between {} and {}", min, max)),
+      (Some(min), None) => Some(format!("at least {}", min)),
+      (None, Some(max)) => Some(format!("at most {}", max)),
+      (None, None) => None,
+    }
+  }
+  
+  /// Checks a parsed value against the declared range. `value` is the text the
+  /// user gave, for error messages
+  pub fn check_range(&self, parsed: &wasmtime::Val, value: &str, i: usize) ->
+  Result<(), HostError> {
+    let below_min = self.min.as_ref().is_some_and(|min| !matches!(
+      compare_to_bound(parsed, min), Some(std::cmp::Ordering::Greater |
+      std::cmp::Ordering::Equal)));
+    let above_max = self.max.as_ref().is_some_and(|max| !matches!(
+      compare_to_bound(parsed, max), Some(std::cmp::Ordering::Less |
+      std::cmp::Ordering::Equal)));
+    
+    if below_min || above_max {
+      // Can .unwrap() because a bound must exist for the value to be outside it
+      return Err(HostError::parameter(ErrorCode::ParameterOutOfRange, Some(i),
+        format!("model generator parameter {} (set to `{}`) must be {}", i + 1,
+        value, self.range().unwrap())));
+    }
+    
+    Ok(())
+  }
+}
+
+/// Compares a parameter value to a bound from emg metadata. Returns None if
+/// they cannot be compared, such as for NaN
+fn compare_to_bound(value: &wasmtime::Val, bound: &serde_json::Number) ->
+Option<std::cmp::Ordering> {
+  // f32 values are compared as f32 so that a bound such as 0.1 does not reject
+  // the f32 closest to 0.1
+  match (value, bound.as_i64()) {
+    (wasmtime::Val::I32(v), Some(b)) => Some(i64::from(*v).cmp(&b)),
+    (wasmtime::Val::I64(v), Some(b)) => Some(v.cmp(&b)),
+    (wasmtime::Val::F32(bits), _) => f32::from_bits(*bits)
+      .partial_cmp(&(bound.as_f64()? as f32)),
+    (wasmtime::Val::F64(bits), _) => f64::from_bits(*bits)
+      .partial_cmp(&bound.as_f64()?),
+    _ => None,
+  }
+}
+
+impl GeneratorMetadata {
+  /// Fallback for .wasm files built without emg metadata. Only the parameter
+  /// types are known
+  pub fn from_types(name: &str, types: &[wasmtime::ValType]) -> Self {
+    Self {
+      name: String::from(name),
+      doc: String::new(),
+      parameters: types.iter().map(|type_| ParameterMetadata {
+        name: None,
+        type_: type_.to_string(),
+        doc: String::new(),
+        enum_: None,
+        variants: Vec::new(),
+        default: None,
+        min: None,
+        max: None,
+      }).collect(),
+    }
+  }
+  
+  /// One-line summary, such as `build_the_model ( height: i32 = 1 )`
+  pub fn signature(&self) -> String {
+    let parameters: Vec<String> = self.parameters.iter().map(|parameter| {
+      let mut summary = match &parameter.name {
+        Some(name) => format!("{}: {}", name, parameter.type_name()),
+        None => String::from(parameter.type_name()),
+      };
+      
+      if let Some(default) = parameter.default_summary() {
+        summary += &format!(" = {}", default);
+      }
+      
+      summary
+    }).collect();
+    
+    format!("{} ( {} )", self.name, parameters.join(", "))
+  }
+  
+  /// Number of parameters that must be given. Parameters after this all have
+  /// defaults
+  pub fn required_parameters(&self) -> usize {
+    self.parameters.iter().rposition(|parameter| parameter.default.is_none())
+      .map_or(0, |i| i + 1)
+  }
+  
+  /// Combines positional and named (`name=value`) parameters into one value
+  /// per parameter, filling in omitted parameters
+  pub fn resolve_parameters(&self, positional: &[String], named: &[String]) ->
+  Result<Vec<String>, HostError> {
+    let parameter_count = self.parameters.len();
+    
+    // Without named parameters, only trailing parameters with defaults may be
+    // omitted, so that positional parameters cannot be silently misplaced
+    let required = if named.is_empty() {
+      self.required_parameters()
+    } else {
+      0
+    };
+    
+    if positional.len() < required || positional.len() > parameter_count {
+      let expected = if required == parameter_count {
+        parameter_count.to_string()
+      } else {
+        format!("{} to {}", required, parameter_count)
+      };
+      
+      return Err(HostError::parameter(ErrorCode::ParameterCount, None,
+        format!("model generator expects {} parameters, but {} were given",
+        expected, positional.len())).in_generator(&self.name));
+    }
+    
+    let mut values: Vec<Option<String>> = self.parameters.iter().enumerate()
+      .map(|(i, _)| positional.get(i).cloned()).collect();
+    
+    for pair in named {
+      let (name, value) = match pair.split_once('=') {
+        Some(split) => split,
+        None => return Err(HostError::parameter(ErrorCode::ParameterType,
+          None, format!("named parameter `{}` should be written as \
+          `name=value`", pair)).in_generator(&self.name)),
+      };
+      
+      let i = match self.parameters.iter().position(|parameter|
+        parameter.name.as_deref() == Some(name)) {
+        Some(i) => i,
+        None => return Err(HostError::parameter(ErrorCode::ParameterCount,
+          None, format!("model generator `{}` has no parameter named `{}`",
+          self.name, name)).in_generator(&self.name)),
+      };
+      
+      if values[i].is_some() {
+        return Err(HostError::parameter(ErrorCode::ParameterCount, Some(i),
+          format!("model generator parameter `{}` was given more than once",
+          name)).in_generator(&self.name));
+      }
+      
+      values[i] = Some(String::from(value));
+    }
+    
+    Ok(values.into_iter().zip(&self.parameters).map(|(value, parameter)| {
+      value.or_else(|| parameter.default_text())
+        .unwrap_or_else(|| parameter.zero_value())
+    }).collect())
+  }
+}
+
+/// Reads an unsigned LEB128 integer, which .wasm files use for sizes
+fn read_leb128(bytes: &[u8], position: &mut usize) -> Option<usize> {
+  let mut result = 0;
+  let mut shift = 0;
+  
+  loop {
+    let byte = *bytes.get(*position)?;
+    *position += 1;
+    
+    // 32-bit values need at most 5 bytes
+    if shift > 28 { return None }
+    
+    result |= ((byte & 0x7f) as usize) << shift;
+    shift += 7;
+    
+    if byte & 0x80 == 0 { return Some(result) }
+  }
+}
+
+/// Returns the contents of every custom section with the given name, joined
+/// together. wasmtime does not expose custom sections, so this reads the .wasm
+/// binary format directly
+pub fn read_custom_sections(wasm: &[u8], name: &str) ->
+Result<Vec<u8>, HostError> {
+  let mut result = Vec::new();
+  
+  // Text format modules have no binary sections to read
+  if !wasm.starts_with(b"\0asm") {
+    return Ok(result);
+  }
+  
+  let malformed = || HostError::module(ErrorCode::WebAssemblyCompile,
+    ".wasm file has a malformed section header");
+  
+  // Sections start after the 4 byte magic and 4 byte version
+  let mut position = 8;
+  while position < wasm.len() {
+    let id = wasm[position];
+    position += 1;
+    
+    let size = read_leb128(wasm, &mut position).ok_or_else(malformed)?;
+    let end = position + size;
+    if end > wasm.len() {
+      return Err(malformed());
+    }
+    
+    // Section 0 is for custom sections, which begin with a name
+    if id == 0 {
+      let mut name_end = position;
+      let name_length = read_leb128(wasm, &mut name_end).ok_or_else(
+        malformed)?;
+      let name_start = name_end;
+      name_end += name_length;
+      
+      if name_end <= end && &wasm[name_start..name_end] == name.as_bytes() {
+        result.extend_from_slice(&wasm[name_end..end]);
+      }
+    }
+    
+    position = end;
+  }
+  
+  Ok(result)
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct LogMessage {
+  /// Name of an emg::LogLevel, such as `Info`
+  pub level: String,
+  pub message: String,
+}
+
+/// Details of the last generate() call, for debugging model generators. Parts
+/// the call did not reach are left empty
+#[derive(Clone, Default, Debug)]
+pub struct CallDiagnostics {
+  /// Arguments passed to the model generator, after parsing
+  pub arguments: Vec<wasmtime::Val>,
+  
+  /// Fuel the model generator used
+  pub fuel_consumed: Option<u64>,
+  
+  /// Where the generated .glb is in WebAssembly memory, and its size
+  pub pointer: Option<i32>,
+  pub size: Option<i32>,
+}
+
+/// Data kept in the wasmtime store, for use by functions the host provides to
+/// WebAssembly
+#[derive(Default)]
+struct HostState {
+  /// Messages logged during the current model generator call
+  logs: Vec<LogMessage>,
+  
+  diagnostics: CallDiagnostics,
+  
+  memory: MemoryLimiter,
+  
+  /// When the current model generator call times out. Other stores on the
+  /// same engine may advance the epoch, so it is checked when they do
+  deadline: Option<std::time::Instant>,
+}
+
+/// Tracks WebAssembly memory growth, and refuses it past Limits::max_memory
+#[derive(Default)]
+struct MemoryLimiter {
+  /// In bytes
+  maximum: Option<usize>,
+  
+  /// Largest size any memory has grown to, in bytes
+  peak: usize,
+  
+  /// Set when growth is refused
+  exceeded: bool,
+}
+
+impl wasmtime::ResourceLimiter for MemoryLimiter {
+  fn memory_growing(&mut self, _current: usize, desired: usize,
+  _maximum: Option<usize>) -> wasmtime::Result<bool> {
+    if self.maximum.is_some_and(|maximum| desired > maximum) {
+      self.exceeded = true;
+      return Ok(false);
+    }
+    
+    self.peak = self.peak.max(desired);
+    Ok(true)
+  }
+  
+  fn table_growing(&mut self, _current: usize, _desired: usize,
+  _maximum: Option<usize>) -> wasmtime::Result<bool> {
+    Ok(true)
+  }
+}
+
+/// Implements the `emg.emg_log` import, which emg::log!() and emg::debug!()
+/// call
+fn emg_log(mut caller: wasmtime::Caller<'_, HostState>, level: i32,
+pointer: i32, length: i32) {
+  let level = match crate::LogLevel::try_from(level) {
+    Ok(level) => format!("{:?}", level),
+    Err(level) => format!("Level {}", level),
+  };
+  
+  let start = pointer as u32 as usize;
+  let end = start + length as u32 as usize;
+  let message = match caller.get_export("memory")
+    .and_then(|export| export.into_memory()) {
+    Some(memory) => match memory.data(&caller).get(start..end) {
+      Some(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+      None => String::from("(log message extends beyond WebAssembly memory)"),
+    },
+    None => String::from("(no WebAssembly memory to read log message from)"),
+  };
+  
+  caller.data_mut().logs.push(LogMessage { level, message });
+}
+
+/// Creates an engine with the settings every module is compiled with.
+/// Precompiled modules can only be loaded by an engine with the same settings
+fn new_engine() -> wasmtime::Engine {
+  // Fuel and epoch interruption must be enabled when the engine is created,
+  // but are only limited during generate()
+  let mut config = wasmtime::Config::new();
+  config.consume_fuel(true).epoch_interruption(true);
+  // Can .unwrap() because these settings are supported on every platform
+  wasmtime::Engine::new(&config).unwrap()
+}
+
+/// Start of .cwasm files written by precompile(). It is followed by the
+/// length of the emg metadata as a little-endian u32, the metadata, and then
+/// the compiled module from wasmtime::Module::serialize()
+pub const CWASM_MAGIC: &[u8] = b"\0emgcwasm";
+
+/// Reads the compiled module and emg metadata from a .cwasm file, without its
+/// magic number. The module must be trusted, see
+/// EMGModule::from_precompiled_file()
+unsafe fn read_cwasm(engine: &wasmtime::Engine, cwasm: &[u8]) ->
+Result<(wasmtime::Module, Vec<u8>), HostError> {
+  let malformed = || HostError::module(ErrorCode::WebAssemblyCompile,
+    ".cwasm file is malformed");
+  
+  // Can .unwrap() because the slice is exactly 4 bytes
+  let length = cwasm.get(..4).ok_or_else(malformed)?;
+  let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+  let metadata = cwasm.get(4..4 + length).ok_or_else(malformed)?;
+  
+  // Deserializing runs machine code from the file without checking it, which
+  // the caller has accepted
+  match wasmtime::Module::deserialize(engine, &cwasm[4 + length..]) {
+    Ok(module) => Ok((module, metadata.to_vec())),
+    Err(e) => Err(HostError::Module { code: ErrorCode::WebAssemblyCompile,
+      message: format!("Unable to load .cwasm file (it may be from a \
+      different version of emg, or for a different CPU): {:?}", e),
+      source: Some(e) }),
+  }
+}
+
+/// Compiles a .wasm file ahead of time, returning the contents of a .cwasm
+/// file that EMGModule::from_precompiled() can load without compiling
+pub fn precompile(wasm: &[u8], cache: &CacheOptions) -> Result<Vec<u8>,
+HostError> {
+  if wasm.starts_with(CWASM_MAGIC) {
+    return Err(HostError::module(ErrorCode::WebAssemblyCompile,
+      "module is already precompiled"));
+  }
+  
+  // Loading the module checks that it is a valid emg module, so that problems
+  // are found now instead of when the .cwasm is used
+  let emg_module = EMGModule::from_bytes(wasm, cache)?;
+  let metadata = read_custom_sections(wasm, "emg")?;
+  
+  let compiled = match emg_module.module.serialize() {
+    Ok(c) => c,
+    Err(e) => return Err(HostError::Module {
+      code: ErrorCode::WebAssemblyCompile,
+      message: format!("Unable to serialize compiled module: {:?}", e),
+      source: Some(e) }),
+  };
+  
+  let mut cwasm = CWASM_MAGIC.to_vec();
+  cwasm.extend((metadata.len() as u32).to_le_bytes());
+  cwasm.extend(metadata);
+  cwasm.extend(compiled);
+  
+  Ok(cwasm)
+}
+
+/// Key for a compiled module in the module cache
+fn module_cache_key(engine: &wasmtime::Engine, wasm: &[u8]) -> String {
+  use sha2::Digest;
+  use std::hash::{Hash, Hasher};
+  
+  // Covers the wasmtime version and every engine setting that affects the
+  // compiled module
+  let mut engine_hasher = std::collections::hash_map::DefaultHasher::new();
+  engine.precompile_compatibility_hash().hash(&mut engine_hasher);
+  
+  let mut hasher = sha2::Sha256::new();
+  hasher.update(engine_hasher.finish().to_le_bytes());
+  hasher.update(wasm);
+  
+  format!("{:x}", hasher.finalize())
+}
+
+/// Compiles a .wasm file, or loads the compiled module from the module cache
+/// if it has been compiled before
+fn compile_module(engine: &wasmtime::Engine, wasm: &[u8], cache: &CacheOptions)
+-> Result<wasmtime::Module, HostError> {
+  let compile = || match wasmtime::Module::new(engine, wasm) {
+    Ok(m) => Ok(m),
+    Err(e) => Err(HostError::Module { code: ErrorCode::WebAssemblyCompile,
+      message: format!("Unable to compile .wasm file: {:?}", e),
+      source: Some(e) }),
+  };
+  
+  let directory = match (cache.no_cache, cache.directory()) {
+    (false, Some(directory)) => directory,
+    _ => return compile(),
+  };
+  
+  let modules = directory.join("modules");
+  let path = modules.join(module_cache_key(engine, wasm));
+  
+  // The cache only contains modules compiled by emg, so can be trusted. If one
+  // is unusable anyway, it is compiled again and replaced
+  if path.exists() {
+    if let Ok(module) = unsafe {
+      wasmtime::Module::deserialize_file(engine, &path)
+    } {
+      touch_cache_entry(&path);
+      return Ok(module);
+    }
+  }
+  
+  let module = compile()?;
+  
+  // The cache is only an optimization, so failing to write it is not an error
+  if let Ok(serialized) = module.serialize() {
+    if std::fs::create_dir_all(&modules).is_ok() {
+      let _ = write_atomically(&path, &serialized);
+      evict_cache(&modules, MAX_CACHE_SIZE);
+    }
+  }
+  
+  Ok(module)
+}
+
+/// Reads a .wasm or .cwasm file
+fn read_module_file(path: &Path) -> Result<Vec<u8>, HostError> {
+  match std::fs::read(path) {
+    Ok(bytes) => Ok(bytes),
+    Err(e) => Err(HostError::IO { path: Some(path.to_path_buf()),
+      message: format!("Unable to read {}: {}", path.display(), e),
+      source: Some(e) }),
+  }
+}
+
+pub struct EMGModule {
+  engine: wasmtime::Engine,
+  module: wasmtime::Module,
+  store: wasmtime::Store<HostState>,
+  instance: wasmtime::Instance,
+  
+  generator_names: Vec<String>,
+  
+  /// Keyed by generator name without the `gen_` prefix
+  metadata: HashMap<String, GeneratorMetadata>,
+}
+
+impl EMGModule {
+  /// Loads a .wasm file. Compiled modules are cached unless `cache.no_cache`
+  /// is set
+  pub fn from_file(wasm: &Path, cache: &CacheOptions) -> Result<Self,
+  HostError> {
+    Self::from_bytes(&read_module_file(wasm)?, cache)
+  }
+  
+  /// Same as from_file(), for a module that is already in memory
+  pub fn from_bytes(bytes: &[u8], cache: &CacheOptions) -> Result<Self,
+  HostError> {
+    if bytes.starts_with(CWASM_MAGIC) {
+      return Err(HostError::module(ErrorCode::WebAssemblyCompile, "module is \
+        precompiled, and can only be loaded by from_precompiled()"));
+    }
+    
+    let engine = new_engine();
+    let module = compile_module(&engine, bytes, cache)?;
+    let metadata = read_custom_sections(bytes, "emg")?;
+    
+    Self::from_module(engine, module, &metadata)
+  }
+  
+  /// Loads a .cwasm file written by precompile()
+  ///
+  /// # Safety
+  ///
+  /// A .cwasm file contains machine code that is run without being checked,
+  /// so it is not sandboxed like a .wasm file. It must come from a trusted
+  /// source, the same as an executable
+  pub unsafe fn from_precompiled_file(cwasm: &Path) -> Result<Self,
+  HostError> {
+    Self::from_precompiled(&read_module_file(cwasm)?)
+  }
+  
+  /// Same as from_precompiled_file(), for a module that is already in memory
+  ///
+  /// # Safety
+  ///
+  /// See from_precompiled_file()
+  pub unsafe fn from_precompiled(cwasm: &[u8]) -> Result<Self, HostError> {
+    let cwasm = match cwasm.strip_prefix(CWASM_MAGIC) {
+      Some(cwasm) => cwasm,
+      None => return Err(HostError::module(ErrorCode::WebAssemblyCompile,
+        "module is not a .cwasm file from precompile()")),
+    };
+    
+    let engine = new_engine();
+    let (module, metadata) = read_cwasm(&engine, cwasm)?;
+    
+    Self::from_module(engine, module, &metadata)
+  }
+  
+  /// Checks that a compiled module is a valid emg module, and reads its
+  /// metadata
+  fn from_module(engine: wasmtime::Engine, module: wasmtime::Module,
+  metadata: &[u8]) -> Result<Self, HostError> {
+    let (store, instance) = Self::instantiate(&engine, &module)?;
+    
+    let mut result = Self { engine, module, store, instance,
+      generator_names: Vec::new(), metadata: HashMap::new() };
+    
+    for name in POINTER_ACCESSORS {
+      result.validate_pointer_accessor(name)?;
+    }
+    
+    if result.instance.get_memory(&mut result.store, "memory").is_none() {
+      return Err(HostError::module(ErrorCode::ModuleNotEMG, ".wasm file is \
+        not a valid emg module: missing required memory export `memory`"));
+    }
+    
+    let mut possible_model_generators = Vec::new();
+    
+    for export in result.instance.exports(&mut result.store) {
+      if export.name().starts_with("gen_") {
+        // Converting to String "launders" the name to break all links with the
+        // original string. Required by borrow checker
+        possible_model_generators.push(String::from(export.name()));
+      }
+    }
+    
+    // Generator validation must be done outside the loop in which possible
+    // generators are found due to borrowing issues related to the store
+    for name in possible_model_generators {
+      result.validate_model_generator(name.as_str())?;
+      result.generator_names.push(name);
+    }
+    
+    result.load_metadata(metadata)?;
+    
+    Ok(result)
+  }
+  
+  /// Creates a fresh instance of a compiled module, with its own memory
+  fn instantiate(engine: &wasmtime::Engine, module: &wasmtime::Module) ->
+  Result<(wasmtime::Store<HostState>, wasmtime::Instance), HostError> {
+    // The "store" seems to be wasmtime's container for instance data
+    let mut store = wasmtime::Store::new(engine, HostState::default());
+    // Can .unwrap() because fuel is enabled
+    store.set_fuel(u64::MAX).unwrap();
+    // The epoch only advances when generate() has a timeout
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(|context| match context.data().deadline {
+      Some(deadline) if std::time::Instant::now() >= deadline =>
+        Err(wasmtime::Trap::Interrupt.into()),
+      _ => Ok(wasmtime::UpdateDeadline::Continue(1)),
+    });
+    store.limiter(|state| &mut state.memory);
+    
+    let mut linker = wasmtime::Linker::new(engine);
+    // Can .unwrap() because emg_log is only defined once
+    linker.func_wrap("emg", "emg_log", emg_log).unwrap();
+    
+    match linker.instantiate(&mut store, module) {
+      Ok(instance) => Ok((store, instance)),
+      Err(e) => Err(HostError::Module { code: ErrorCode::WebAssemblyInstance,
+        message: format!("Unable to instantiate WebAssembly module: {:?}", e),
+        source: Some(e) }),
+    }
+  }
+  
+  /// Replaces the instance with a fresh one, so that the next model generator
+  /// call is not affected by earlier ones. The module is not recompiled
+  pub fn reinstantiate(&mut self) -> Result<(), HostError> {
+    let (store, instance) = Self::instantiate(&self.engine, &self.module)?;
+    self.store = store;
+    self.instance = instance;
+    
+    Ok(())
+  }
+  
+  /// Makes a copy with its own instance, sharing the compiled module, so that
+  /// model generators can run on several threads at once
+  pub fn fork(&self) -> Result<Self, HostError> {
+    let (store, instance) = Self::instantiate(&self.engine, &self.module)?;
+    
+    Ok(Self { engine: self.engine.clone(), module: self.module.clone(), store,
+      instance, generator_names: self.generator_names.clone(),
+      metadata: self.metadata.clone() })
+  }
+  
+  /// Reads metadata from the contents of a module's "emg" custom sections
+  fn load_metadata(&mut self, section: &[u8]) -> Result<(), HostError> {
+    let mut enums = HashMap::new();
+    
+    for line in section.split(|byte| *byte == b'\n') {
+      if line.is_empty() { continue }
+      
+      match serde_json::from_slice(line) {
+        Ok(MetadataLine::Generator(metadata)) => {
+          self.metadata.insert(metadata.name.clone(), metadata);
+        },
+        Ok(MetadataLine::Enum(metadata)) => {
+          enums.insert(metadata.name.clone(), metadata);
+        },
+        Err(e) => return Err(HostError::module(ErrorCode::ModuleNotEMG,
+          format!(".wasm file is not a valid emg module: unable to read emg \
+          metadata: {}", e))),
+      }
+    }
+    
+    for metadata in self.metadata.values_mut() {
+      for parameter in &mut metadata.parameters {
+        let enum_name = match &parameter.enum_ {
+          Some(name) => name,
+          None => continue,
+        };
+        
+        match enums.get(enum_name) {
+          Some(enum_metadata) => parameter.variants = enum_metadata.variants
+            .clone(),
+          None => return Err(HostError::module(ErrorCode::ModuleNotEMG,
+            format!(".wasm file is not a valid emg module: emg metadata does \
+            not list the variants of enum `{}`", enum_name))),
+        }
+      }
+    }
+    
+    for name in self.generator_names.clone() {
+      let types = self.parameter_types(&name);
+      
+      let metadata = self.metadata.entry(String::from(&name[4..]))
+        .or_insert_with(|| GeneratorMetadata::from_types(&name[4..], &types));
+      
+      let metadata_types: Vec<&str> = metadata.parameters.iter()
+        .flat_map(|parameter| parameter.wasm_types()).collect();
+      let types: Vec<String> = types.iter().map(|type_| type_.to_string())
+        .collect();
+      
+      if metadata_types != types {
+        return Err(HostError::module(ErrorCode::ModuleNotEMG, format!(".wasm \
+          file is not a valid emg module: emg metadata gives parameter types \
+          ( {} ) for `{}()`, but it accepts ( {} )", metadata_types.join(", "),
+          name, types.join(", "))));
+      }
+    }
+    
+    // Metadata for functions that are not exported cannot be used
+    let generator_names = &self.generator_names;
+    self.metadata.retain(|name, _| generator_names.contains(
+      &format!("gen_{}", name)));
+    
+    Ok(())
+  }
+  
+  fn validate_model_generator(&mut self, name: &str) -> Result<(), HostError> {
+    let export = match self.instance.get_export(&mut self.store, name) {
+      Some(function) => function,
+      None => return Err(HostError::GeneratorNotFound {
+        generator: String::from(&name[4..]),
+        message: format!(".wasm file does not contain model generator `{}`",
+        name) }),
+    };
+    
+    let generator = match export.into_func() {
+      Some(function) => function,
+      None => return Err(HostError::module(ErrorCode::ModuleNotEMG, format!(
+        ".wasm file is not a valid emg module: export `{}` must be a \
+        function", name))),
+    };
+    
+    if generator.ty(&self.store).results().len() != 1 {
+      return Err(HostError::module(ErrorCode::ModuleNotEMG, format!(".wasm \
+        file is not a valid emg module: function `{}()` must return one \
+        result", name)));
+    }
+    // .unwrap() acceptable here because the length is asserted == 1
+    match generator.ty(&self.store).results().next().unwrap() {
+      wasmtime::ValType::I32 => {},
+      _ => return Err(HostError::module(ErrorCode::ModuleNotEMG, format!(
+        ".wasm file is not a valid emg module: function `{}()` must return a \
+        32-bit integer", name))),
+    }
+    
+    Ok(())
+  }
+  
+  fn validate_pointer_accessor(&mut self, name: &str) -> Result<(), HostError> {
+    let export = match self.instance.get_export(&mut self.store, name) {
+      Some(function) => function,
+      None => return Err(HostError::module(ErrorCode::ModuleNotEMG, format!(
+        ".wasm file is not a valid emg module: missing required function \
+        `{}()`", name))),
+    };
+    
+    let accessor = match export.into_func() {
+      Some(function) => function,
+      None => return Err(HostError::module(ErrorCode::ModuleNotEMG, format!(
+        ".wasm file is not a valid emg module: export `{}` must be a \
+        function", name))),
+    };
+    
+    if accessor.ty(&self.store).params().len() != 0 {
+      return Err(HostError::module(ErrorCode::ModuleNotEMG, format!(".wasm \
+        file is not a valid emg module: function `{}()` must not accept any \
+        arguments", name)));
+    }
+    if accessor.ty(&self.store).results().len() != 1 {
+      return Err(HostError::module(ErrorCode::ModuleNotEMG, format!(".wasm \
+        file is not a valid emg module: function `{}()` must return one \
+        result", name)));
+    }
+    // .unwrap() acceptable here because the length is asserted == 1
+    match accessor.ty(&self.store).results().next().unwrap() {
+      wasmtime::ValType::I32 => {},
+      _ => return Err(HostError::module(ErrorCode::ModuleNotEMG, format!(
+        ".wasm file is not a valid emg module: function `{}()` must return a \
+        32-bit integer", name))),
+    }
+    
+    Ok(())
+  }
+  
+  /// Metadata for each model generator, sorted by name. Export order depends
+  /// on the linker, so is not used
+  pub fn generators(&self) -> Vec<&GeneratorMetadata> {
+    let mut generators: Vec<&GeneratorMetadata> = self.metadata.values()
+      .collect();
+    generators.sort_by(|a, b| a.name.cmp(&b.name));
+    generators
+  }
+  
+  /// Metadata for a model generator. `name` is given without the `gen_`
+  /// prefix
+  pub fn generator(&self, name: &str) -> Option<&GeneratorMetadata> {
+    self.metadata.get(name)
+  }
+  
+  /// Removes and returns the messages logged during the last generate() call
+  pub fn take_logs(&mut self) -> Vec<LogMessage> {
+    std::mem::take(&mut self.store.data_mut().logs)
+  }
+  
+  /// Details of the last generate() call
+  pub fn diagnostics(&self) -> &CallDiagnostics {
+    &self.store.data().diagnostics
+  }
+  
+  /// Minimum and maximum size of the `memory` export, in 64 KiB pages
+  pub fn memory_pages(&mut self) -> (u64, Option<u64>) {
+    // Can .unwrap() because from_bytes() checked for a "memory" export
+    let memory = self.instance.get_memory(&mut self.store, "memory").unwrap()
+      .ty(&self.store);
+    
+    (memory.minimum(), memory.maximum())
+  }
+  
+  /// Largest size WebAssembly memory has reached, in bytes
+  pub fn peak_memory(&self) -> usize {
+    self.store.data().memory.peak
+  }
+  
+  /// Current size of the `memory` export, in bytes
+  pub fn memory_size(&mut self) -> usize {
+    // Can .unwrap() because from_bytes() checked for a "memory" export
+    self.instance.get_memory(&mut self.store, "memory").unwrap()
+      .data_size(&self.store)
+  }
+  
+  /// Parameter types of a model generator. `name` must be one of
+  /// .generator_names
+  fn parameter_types(&mut self, name: &str) -> Vec<wasmtime::ValType> {
+    // Can .unwrap() because these functions were already validated
+    let generator = self.instance.get_func(&mut self.store, name).unwrap();
+    
+    generator.ty(&self.store).params().collect()
+  }
+  
+  /// Runs a model generator and returns a copy of the .glb it wrote into
+  /// WebAssembly memory. `name` is given without the `gen_` prefix
+  pub fn generate(&mut self, name: &str, parameters: &[String],
+  named_parameters: &[String], limits: &Limits) ->
+  Result<Vec<u8>, HostError> {
+    let store = &mut self.store;
+    let instance = self.instance;
+    
+    store.data_mut().logs.clear();
+    store.data_mut().diagnostics = CallDiagnostics::default();
+    
+    let generator = match instance.get_func(&mut *store,
+      (String::from("gen_") + name).as_str(),
+    ) {
+      Some(f) => f,
+      None => return Err(HostError::GeneratorNotFound {
+        generator: String::from(name),
+        message: format!(".wasm file does not contain model generator `{}`",
+        name) }),
+    };
+    
+    // Can .unwrap() because load_metadata() fills in metadata for every
+    // generator
+    let metadata = self.metadata.get(name).unwrap();
+    let values = metadata.resolve_parameters(parameters, named_parameters)?;
+    
+    let wasm_types: Vec<wasmtime::ValType> = generator.ty(&*store).params()
+      .collect();
+    
+    let mut generator_args: Vec<wasmtime::Val> = Vec::new();
+    for (i, parameter) in metadata.parameters.iter().enumerate() {
+      if parameter.type_ == "string" {
+        generator_args.extend(copy_string(store, instance, name,
+          &values[i])?);
+        continue;
+      }
+      
+      // Strings take two WebAssembly parameters, so the index into wasm_types
+      // can differ from i
+      let type_ = &wasm_types[generator_args.len()];
+      let parsed = parse_parameter(parameter, type_, &values[i], i)
+        .and_then(|parsed| parameter.check_range(&parsed, &values[i], i)
+        .map(|_| parsed))
+        .map_err(|e| e.in_generator(name))?;
+      generator_args.push(parsed);
+    }
+    
+    store.data_mut().diagnostics.arguments = generator_args.clone();
+    
+    let fuel = limits.fuel.unwrap_or(u64::MAX);
+    // Can .unwrap() because fuel is enabled
+    store.set_fuel(fuel).unwrap();
+    
+    store.data_mut().memory.maximum = limits.max_memory;
+    store.data_mut().memory.exceeded = false;
+    
+    // The timer advances the epoch, which interrupts the model generator once
+    // its deadline has passed, unless it is cancelled by dropping cancel_timer
+    // first
+    let (cancel_timer, timer_cancelled) = std::sync::mpsc::channel::<()>();
+    let timer = limits.timeout.map(|timeout| {
+      let engine = self.engine.clone();
+      let timeout = std::time::Duration::from_millis(timeout);
+      store.data_mut().deadline = Some(std::time::Instant::now() + timeout);
+      
+      std::thread::spawn(move || {
+        if let Err(std::sync::mpsc::RecvTimeoutError::Timeout) =
+          timer_cancelled.recv_timeout(timeout) {
+          engine.increment_epoch();
+        }
+      })
+    });
+    
+    let mut result = [wasmtime::Val::from(0)];
+    let call_result = generator.call(&mut *store, &generator_args, &mut result);
+    
+    drop(cancel_timer);
+    if let Some(timer) = timer {
+      // Can .unwrap() because the timer thread cannot panic
+      timer.join().unwrap();
+      store.data_mut().deadline = None;
+      store.set_epoch_deadline(1);
+    }
+    
+    // Can .unwrap() because fuel is enabled
+    store.data_mut().diagnostics.fuel_consumed = Some(fuel -
+      store.get_fuel().unwrap());
+    // Later calls only read the output, so are not limited
+    store.set_fuel(u64::MAX).unwrap();
+    store.data_mut().memory.maximum = None;
+    
+    match call_result {
+      Ok(_) => {},
+      // Refused memory growth usually makes the model generator abort, so the
+      // limit is the most helpful thing to report
+      Err(e) if store.data().memory.exceeded => return Err(
+        HostError::Execution { generator: String::from(name),
+        message: format!("model generator exceeded the memory limit ({} \
+        bytes)", limits.max_memory.unwrap_or(0)), source: Some(e) }),
+      Err(e) => return Err(HostError::Execution {
+        generator: String::from(name),
+        message: match e.downcast_ref::<wasmtime::Trap>() {
+          // Can .unwrap() because there is only a deadline with a timeout
+          Some(wasmtime::Trap::Interrupt) => format!("model generator \
+            exceeded the time limit ({} ms)", limits.timeout.unwrap()),
+          Some(wasmtime::Trap::OutOfFuel) => format!("model generator ran \
+            out of fuel (limit: {})", fuel),
+          _ => format!("WebAssembly execution failed: {:?}", e),
+        },
+        source: Some(e),
+      }),
+    }
+    // .unwrap() acceptable here because the type was previously asserted
+    match result[0].i32().unwrap() {
+      0 => {},
+      // The code is passed through as-is, even if it is not an ErrorCode
+      e => return Err(HostError::Generation { generator: String::from(name),
+        code: e, message: read_error_message(store, instance) }),
+    }
+    
+    // Can use .unwrap() because validator checked this exists
+    let get_pointer = instance.get_func(&mut *store, "model_pointer").unwrap();
+    let mut pointer = [wasmtime::Val::from(0)];
+    match get_pointer.call(&mut *store, &[], &mut pointer) {
+      Ok(_) => {},
+      Err(e) => return Err(HostError::Execution {
+        generator: String::from(name),
+        message: format!("Unable to retrieve model: {:?}", e),
+        source: Some(e) }),
+    }
+    store.data_mut().diagnostics.pointer = pointer[0].i32();
+    
+    // Can use .unwrap() because validator checked this exists
+    let get_size = instance.get_func(&mut *store, "model_size").unwrap();
+    let mut size = [wasmtime::Val::from(0)];
+    match get_size.call(&mut *store, &[], &mut size) {
+      Ok(_) => {},
+      Err(e) => return Err(HostError::Execution {
+        generator: String::from(name),
+        message: format!("Unable to retrieve model: {:?}", e),
+        source: Some(e) }),
+    }
+    store.data_mut().diagnostics.size = size[0].i32();
+    
+    // Can .unwrap() because from_bytes() checked for a "memory" export
+    let memory = instance.get_memory(&mut *store, "memory").unwrap();
+    // Can .unwrap() because the types of pointer and size were asserted earlier
+    let pointer_plain_int = pointer[0].i32().unwrap() as usize;
+    let size_plain_int = size[0].i32().unwrap() as usize;
+    match memory.data(&*store).get(pointer_plain_int..pointer_plain_int +
+      size_plain_int) {
+      Some(memory_of_interest) => Ok(memory_of_interest.to_vec()),
+      None => Err(HostError::output(ErrorCode::OutputNotGLB, None, format!(
+        "Generated output ({} bytes at {}) extends beyond WebAssembly memory",
+        size_plain_int, pointer_plain_int))),
+    }
+  }
+}
+
+/// Reads the message a model generator wrote with its error, if any. Modules
+/// built before error messages were added lack error_pointer() and
+/// error_size(), so they are optional
+fn read_error_message(store: &mut wasmtime::Store<HostState>,
+instance: wasmtime::Instance) -> Option<String> {
+  let get_pointer = instance.get_typed_func::<(), i32>(&mut *store,
+    "error_pointer").ok()?;
+  let get_size = instance.get_typed_func::<(), i32>(&mut *store, "error_size")
+    .ok()?;
+  
+  let pointer = get_pointer.call(&mut *store, ()).ok()? as u32 as usize;
+  let size = get_size.call(&mut *store, ()).ok()? as u32 as usize;
+  if size == 0 {
+    return None;
+  }
+  
+  // Can .unwrap() because from_bytes() checked for a "memory" export
+  let memory = instance.get_memory(&mut *store, "memory").unwrap();
+  let bytes = memory.data(&*store).get(pointer..pointer + size)?;
+  
+  Some(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Copies a string parameter into WebAssembly memory allocated by the module's
+/// `emg_alloc()`. Returns the pointer and length to pass to the model generator
+fn copy_string(store: &mut wasmtime::Store<HostState>,
+instance: wasmtime::Instance, generator: &str, value: &str) ->
+Result<[wasmtime::Val; 2], HostError> {
+  let alloc = match instance.get_typed_func::<i32, i32>(&mut *store,
+    "emg_alloc") {
+    Ok(f) => f,
+    Err(_) => return Err(HostError::module(ErrorCode::ModuleNotEMG,
+      ".wasm file is not a valid emg module: string parameters require \
+      function `emg_alloc(i32) -> i32`")),
+  };
+  
+  let length = value.len() as i32;
+  let pointer = match alloc.call(&mut *store, length) {
+    Ok(p) => p,
+    Err(e) => return Err(HostError::Execution {
+      generator: String::from(generator),
+      message: format!("Unable to allocate WebAssembly memory for string \
+      parameter: {:?}", e),
+      source: Some(e) }),
+  };
+  
+  // Can .unwrap() because from_bytes() checked for a "memory" export
+  let memory = instance.get_memory(&mut *store, "memory").unwrap();
+  match memory.write(&mut *store, pointer as u32 as usize, value.as_bytes()) {
+    Ok(_) => {},
+    Err(_) => return Err(HostError::Execution {
+      generator: String::from(generator),
+      message: format!("emg_alloc() returned memory ({} bytes at {}) that \
+      extends beyond WebAssembly memory", length, pointer),
+      source: None }),
+  }
+  
+  Ok([wasmtime::Val::from(pointer), wasmtime::Val::from(length)])
+}
+
+fn parse_parameter(parameter: &ParameterMetadata, type_: &wasmtime::ValType,
+value: &String, i: usize) -> Result<wasmtime::Val, HostError> {
+  match parameter.type_.as_str() {
+    "bool" => return match value.as_str() {
+      "true" => Ok(wasmtime::Val::from(1)),
+      "false" => Ok(wasmtime::Val::from(0)),
+      _ => Err(HostError::parameter(ErrorCode::ParameterType, Some(i),
+        format!("model generator parameter {} (set to `{}`) should be `true` \
+        or `false`", i + 1, value))),
+    },
+    
+    "color" => {
+      let hex = value.strip_prefix('#').unwrap_or(value);
+      
+      return match i32::from_str_radix(hex, 16) {
+        Ok(v) if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit())
+          => Ok(wasmtime::Val::from(v)),
+        _ => Err(HostError::parameter(ErrorCode::ParameterType, Some(i),
+          format!("model generator parameter {} (set to `{}`) should be a \
+          color written as `#rrggbb`", i + 1, value))),
+      };
+    },
+    
+    "enum" => return match parameter.variants.iter()
+      .position(|variant| variant == value) {
+      Some(v) => Ok(wasmtime::Val::from(v as i32)),
+      None => Err(HostError::parameter(ErrorCode::ParameterType, Some(i),
+        format!("model generator parameter {} (set to `{}`) should be one of \
+        `{}`", i + 1, value, parameter.variants.join("`, `")))),
+    },
+    
+    _ => {},
+  }
+  
+  match type_ {
+    wasmtime::ValType::I32 => match value.parse::<i32>() {
+      Ok(v) => Ok(wasmtime::Val::from(v)),
+      Err(_) => Err(HostError::parameter(ErrorCode::ParameterType, Some(i),
+        format!("model generator parameter {} (set to `{}`) should be a \
+        32-bit integer", i + 1, value))),
+    },
+    
+    wasmtime::ValType::I64 => match value.parse::<i64>() {
+      Ok(v) => Ok(wasmtime::Val::from(v)),
+      Err(_) => Err(HostError::parameter(ErrorCode::ParameterType, Some(i),
+        format!("model generator parameter {} (set to `{}`) should be a \
+        64-bit integer", i + 1, value))),
+    },
+    
+    wasmtime::ValType::F32 => match value.parse::<f32>() {
+      Ok(v) => Ok(wasmtime::Val::from(v)),
+      Err(_) => Err(HostError::parameter(ErrorCode::ParameterType, Some(i),
+        format!("model generator parameter {} (set to `{}`) should be a \
+        32-bit floating-point value", i + 1, value))),
+    },
+    
+    wasmtime::ValType::F64 => match value.parse::<f64>() {
+      Ok(v) => Ok(wasmtime::Val::from(v)),
+      Err(_) => Err(HostError::parameter(ErrorCode::ParameterType, Some(i),
+        format!("model generator parameter {} (set to `{}`) should be a \
+        64-bit floating-point value", i + 1, value))),
+    },
+    
+    _ => Err(HostError::parameter(ErrorCode::ParameterType, Some(i),
+      "emg model generators only support parameters of type i32, \
+      i64, f32, or f64")),
+  }
+}
+
+pub struct ChunkMetadata {
+  pub start: u32,
+  pub end: u32,
+}
+
+pub struct GLBMetadata {
+  pub json: ChunkMetadata,
+  pub bin: Option<ChunkMetadata>,
+}
+
+impl GLBMetadata {
+  pub fn from_glb(glb: &[u8]) -> Result<Self, HostError> {
+    if glb.len() < 12 {
+      return Err(HostError::output(ErrorCode::OutputNotGLB, None, format!(
+        "Generated output is too small ({} bytes) to contain required .glb \
+        header (12 bytes)", glb.len())));
+    }
+    
+    // Can .unwrap() because .glb size was just checked
+    let magic = String::from_utf8_lossy(glb[0..4].try_into().unwrap());
+    let version = u32::from_le_bytes(glb[ 4.. 8].try_into().unwrap());
+    let length  = u32::from_le_bytes(glb[ 8..12].try_into().unwrap());
+    
+    if magic != "glTF" {
+      return Err(HostError::output(ErrorCode::OutputNotGLB, None, format!(
+        "Generated output does not begin with magic bytes `glTF` required in \
+        .glb files (has `{}` instead)", magic)));
+    }
+    
+    if version != 2 {
+      return Err(HostError::output(ErrorCode::NotImplemented, None, format!(
+        "Generated output gives a .glb container version of {}, but this tool \
+        only supports version 2", version)));
+    }
+    
+    if length != glb.len() as u32 {
+      return Err(HostError::output(ErrorCode::OutputNotGLB, None, format!(
+        "Header in generated output gives a length of {} bytes, but output is \
+        {} bytes", length, glb.len())));
+    }
+    
+    let json_length = Self::validate_chunk(glb[12..].try_into().unwrap(),
+      String::from("JSON"))?;
+    
+    let json_metadata = ChunkMetadata { start: 20, end: 12 + json_length };
+    
+    if length == json_metadata.end {
+      return Ok(GLBMetadata { json: json_metadata, bin: None });
+    }
+    
+    let bin_length = Self::validate_chunk(
+      glb[json_metadata.end as usize..].try_into().unwrap(),
+      String::from("BIN\0"))?;
+    
+    let bin_metadata = ChunkMetadata { start: json_metadata.end + 8, end:
+      json_metadata.end + bin_length };
+    
+    if bin_metadata.end < length {
+      return Err(HostError::output(ErrorCode::NotImplemented, None, format!(
+        "Generated output contains additional space ({} bytes) after the JSON \
+        and BIN chunks, but this tool does not support any other chunk types",
+        length - bin_metadata.end)));
+    }
+    
+    Ok(GLBMetadata { json: json_metadata, bin: Some(bin_metadata) })
+  }
+  
+  /// Returns size of chunk if valid
+  fn validate_chunk(chunk: &[u8], expected_type: String) -> Result<u32,
+  HostError> {
+    if chunk.len() < 8 {
+      return Err(HostError::output(ErrorCode::OutputNotGLB,
+        Some(&expected_type), format!("Genrated out does not have enough \
+        space remaining for `{}` header ({} bytes remain but .glb chunk \
+        headers are 8 bytes)", expected_type, chunk.len())));
+    }
+    
+    // Can .unwrap() because .glb size was just checked
+    let data_length = u32::from_le_bytes(chunk[0..4].try_into().unwrap());
+    let chunk_type = String::from_utf8_lossy(chunk[4..8].try_into().unwrap());
+    
+    if chunk_type != expected_type {
+      return Err(HostError::output(ErrorCode::OutputNotGLB, Some(&chunk_type),
+        format!("Found `{}` chunk in generated output where `{}` chunk was \
+        expected", chunk_type, expected_type)));
+    }
+    
+    if (chunk.len() as u32) < (8 + data_length) {
+      return Err(HostError::output(ErrorCode::OutputNotGLB, Some(&chunk_type),
+        format!("`{}` chunk header in generated output gives a length of {} \
+        bytes, but output is only long enough for up to {} bytes", chunk_type,
+        data_length, chunk.len() - 8)));
+    }
+    
+    if data_length % 4 > 0 {
+      return Err(HostError::output(ErrorCode::OutputNotGLB, Some(&chunk_type),
+        format!("`{}` chunk in generated output is {} bytes, but all chunks \
+        in .glb files must be multiples of 4 bytes", chunk_type,
+        data_length + 8)));
+    }
+    
+    Ok(data_length + 8)
+  }
+}
+
+/// Converts a .glb from a model generator into the requested format. For
+/// Format::GLTFSeparate, the output refers to `bin_uri` instead of embedding
+/// the BIN chunk, and the BIN chunk is returned separately to be written there
+pub fn format_output(glb: Vec<u8>, format: &Format, bin_uri: Option<&str>) ->
+Result<(Vec<u8>, Option<Vec<u8>>), HostError> {
+  let glb_metadata = GLBMetadata::from_glb(&glb)?;
+  
+  let mut parsed: serde_json::Value = match serde_json::de::from_slice(
+    &glb[glb_metadata.json.start as usize..glb_metadata.json.end as usize]) {
+    Ok(json) => json,
+    Err(e) => return Err(HostError::Output { code: ErrorCode::OutputNotGLB,
+      chunk_type: Some(String::from("JSON")),
+      message: format!("model generated with invalid JSON: {:?}", e),
+      source: Some(e) }),
+  };
+  
+  match format {
+    // Can .unwrap() because this JSON was just parsed, so it must be valid
+    Format::Pretty => {
+      Ok(((serde_json::to_string_pretty(&parsed).unwrap() + "\n").into_bytes(),
+        None))
+    },
+    Format::GLTF | Format::GLTFSeparate => {
+      let mut external_bin = None;
+      
+      match glb_metadata.bin {
+        None => {},
+        Some(bin_metadata) => {
+          let buffers = match parsed.get_mut("buffers") {
+            Some(v) => v,
+            None => return Err(HostError::output(ErrorCode::OutputNotGLB,
+              Some("JSON"), "No `buffers` field present in generated output")),
+          };
+          
+          let buffer_0 = match buffers.get_mut(0) {
+            Some(v) => v,
+            None => return Err(HostError::output(ErrorCode::OutputNotGLB,
+              Some("JSON"), "`buffers` field in generated output has no \
+              entries")),
+          };
+          
+          let buffer_0_as_object = match buffer_0.as_object_mut() {
+            Some(v) => v,
+            None => return Err(HostError::output(ErrorCode::OutputNotGLB,
+              Some("JSON"), "`buffer[0]` field in generated output is not an \
+              object")),
+          };
+          
+          let bin = &glb[bin_metadata.start as usize..
+            bin_metadata.end as usize];
+          
+          let uri = match bin_uri {
+            Some(uri) => {
+              external_bin = Some(bin.to_vec());
+              String::from(uri)
+            },
+            None => {
+              let mut base64_buffer = String::from("data:application/\
+                octet-stream;base64,");
+              
+              base64::engine::general_purpose::STANDARD.encode_string(bin,
+                &mut base64_buffer);
+              
+              base64_buffer
+            },
+          };
+          
+          buffer_0_as_object.insert(String::from("uri"),
+            serde_json::Value::String(uri));
+        },
+      }
+      
+      Ok(((serde_json::to_string(&parsed).unwrap() + "\n").into_bytes(),
+        external_bin))
+    },
+    Format::GLB => Ok((glb, None)),
+  }
+}
+
+/// Writes to a temporary file first and then renames it, so that readers of
+/// `path` never see a partially written file
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), HostError> {
+  let mut temporary = path.as_os_str().to_owned();
+  temporary.push(".tmp");
+  
+  match std::fs::write(&temporary, contents) {
+    Ok(_) => {},
+    Err(e) => return Err(HostError::IO { path: Some(PathBuf::from(&temporary)),
+      message: format!("Unable to write {}: {}",
+      Path::new(&temporary).display(), e), source: Some(e) }),
+  }
+  
+  match std::fs::rename(&temporary, path) {
+    Ok(_) => Ok(()),
+    Err(e) => Err(HostError::IO { path: Some(path.to_path_buf()),
+      message: format!("Unable to move {} to {}: {}",
+      Path::new(&temporary).display(), path.display(), e), source: Some(e) }),
+  }
+}
+
@@ -2,11 +2,24 @@ use rstest::{rstest, fixture};
 use assert_cmd::prelude::*;
 //use predicates::prelude::*;
 use std::process::Command;
+use std::io::{Read, Write};
 use emg::ErrorCode;
 
 #[fixture]
 fn cmd() -> std::process::Command {
-  std::process::Command::cargo_bin("emg").unwrap()
+  let mut cmd = std::process::Command::cargo_bin("emg").unwrap();
+  
+  // Every test gets an empty output cache, so that earlier runs cannot hide
+  // model generator behavior
+  static CACHES: std::sync::atomic::AtomicUsize =
+    std::sync::atomic::AtomicUsize::new(0);
+  let cache = std::env::temp_dir().join(format!("emg-test-cache-{}-{}",
+    std::process::id(),
+    CACHES.fetch_add(1, std::sync::atomic::Ordering::Relaxed)));
+  let _ = std::fs::remove_dir_all(&cache);
+  cmd.env("EMG_CACHE_DIR", cache);
+  
+  cmd
 }
 
 ///////////////////
@@ -40,6 +53,183 @@ fn gen_sunny_day_pretty(mut cmd: Command) {
      .assert().code(ErrorCode::None as i32).stdout(expected);
 }
 
+/// Replaces a file by renaming a complete copy over it, so that a watcher
+/// never sees it partly written
+fn replace_file(path: &std::path::Path, contents: &[u8]) {
+  let temp = path.with_extension("tmp");
+  std::fs::write(&temp, contents).unwrap();
+  std::fs::rename(&temp, path).unwrap();
+}
+
+/// Waits for the next line a watcher writes to stderr about a regeneration,
+/// and returns its first word
+fn next_regeneration(lines: &std::sync::mpsc::Receiver<String>) -> String {
+  while let Ok(line) = lines.recv_timeout(std::time::Duration::from_secs(10)) {
+    if line.starts_with("Wrote ") || line.starts_with("Error: ") {
+      return line.split(' ').next().unwrap().to_string();
+    }
+  }
+  
+  String::from("(timed out)")
+}
+
+#[rstest]
+fn gen_watch(mut cmd: Command) {
+  use std::io::BufRead;
+  
+  let expected = std::fs::read("tests/build_the_model.glb").unwrap();
+  let wasm_bytes = std::fs::read("examples/blocks.wasm").unwrap();
+  let dir = std::env::temp_dir().join("emg-test-gen-watch");
+  let wasm = dir.join("blocks.wasm");
+  let output = dir.join("output.glb");
+  let _ = std::fs::remove_dir_all(&dir);
+  std::fs::create_dir_all(&dir).unwrap();
+  std::fs::write(&wasm, &wasm_bytes).unwrap();
+  
+  let mut watcher = cmd.arg("gen").arg(&wasm)
+    .arg("build_the_model").arg("1").arg("--output").arg(&output).arg("--watch")
+    .stderr(std::process::Stdio::piped()).spawn().unwrap();
+  
+  let (sender, lines) = std::sync::mpsc::channel();
+  let stderr = std::io::BufReader::new(watcher.stderr.take().unwrap());
+  std::thread::spawn(move || {
+    for line in stderr.lines().map_while(Result::ok) {
+      if sender.send(line).is_err() { break }
+    }
+  });
+  
+  let first = next_regeneration(&lines);
+  let first_output = std::fs::read(&output).ok();
+  
+  // A broken rebuild is reported, and the last good model stays in place
+  replace_file(&wasm, &std::fs::read("tests/invalid.wasm").unwrap());
+  let broken = next_regeneration(&lines);
+  let broken_output = std::fs::read(&output).ok();
+  
+  // Fixing the .wasm file regenerates the model
+  let _ = std::fs::remove_file(&output);
+  replace_file(&wasm, &wasm_bytes);
+  let fixed = next_regeneration(&lines);
+  let fixed_output = std::fs::read(&output).ok();
+  
+  watcher.kill().unwrap();
+  watcher.wait().unwrap();
+  
+  assert_eq!(first, "Wrote");
+  assert_eq!(first_output.as_ref(), Some(&expected));
+  assert_eq!(broken, "Error:");
+  assert_eq!(broken_output.as_ref(), Some(&expected));
+  assert_eq!(fixed, "Wrote");
+  assert_eq!(fixed_output.as_ref(), Some(&expected));
+}
+
+#[rstest]
+fn gen_watch_without_output(mut cmd: Command) {
+  cmd.arg("gen").arg("examples/blocks.wasm")
+     .arg("build_the_model").arg("1").arg("--watch")
+     .assert().failure().stdout("");
+}
+
+#[rstest]
+#[case("emg-test-output.glb", &[], "tests/build_the_model.glb")]
+#[case("emg-test-output.gltf", &[], "tests/build_the_model.gltf")]
+#[case("emg-test-output-2.gltf", &["-f", "glb"], "tests/build_the_model.glb")]
+fn gen_output(mut cmd: Command, #[case] name: &str, #[case] args: &[&str],
+#[case] expected: &str) {
+  let expected = std::fs::read(expected).unwrap();
+  let output = std::env::temp_dir().join(name);
+  let _ = std::fs::remove_file(&output);
+  
+  cmd.arg("gen").arg("examples/blocks.wasm")
+     .arg("build_the_model").arg("1").arg("--output").arg(&output).args(args)
+     .assert().code(ErrorCode::None as i32).stdout("");
+  
+  assert_eq!(std::fs::read(&output).unwrap(), expected);
+}
+
+#[rstest]
+#[case("emg-test-external-buffers", &["--external-buffers"])]
+#[case("emg-test-gltf-separate", &["-f", "gltf-separate"])]
+fn gen_output_external_buffers(mut cmd: Command, #[case] name: &str,
+#[case] args: &[&str]) {
+  let output = std::env::temp_dir().join(format!("{}.gltf", name));
+  let bin = std::env::temp_dir().join(format!("{}.bin", name));
+  let _ = std::fs::remove_file(&output);
+  let _ = std::fs::remove_file(&bin);
+  
+  cmd.arg("gen").arg("examples/blocks.wasm")
+     .arg("build_the_model").arg("1").arg("--output").arg(&output).args(args)
+     .assert().code(ErrorCode::None as i32).stdout("");
+  
+  let embedded: serde_json::Value = serde_json::from_slice(
+    &std::fs::read("tests/build_the_model.gltf").unwrap()).unwrap();
+  let mut external: serde_json::Value = serde_json::from_slice(
+    &std::fs::read(&output).unwrap()).unwrap();
+  
+  assert_eq!(external["buffers"][0]["uri"], format!("{}.bin", name));
+  
+  // Other than the buffer URI, the output should match the embedded version
+  external["buffers"][0]["uri"] = embedded["buffers"][0]["uri"].clone();
+  assert_eq!(external, embedded);
+  
+  use base64::Engine;
+  let embedded_uri = embedded["buffers"][0]["uri"].as_str().unwrap();
+  assert_eq!(std::fs::read(&bin).unwrap(),
+    base64::engine::general_purpose::STANDARD.decode(embedded_uri
+    .strip_prefix("data:application/octet-stream;base64,").unwrap()).unwrap());
+}
+
+#[rstest]
+#[case(&["--external-buffers"])]
+#[case(&["--format", "gltf-separate"])]
+fn gen_external_buffers_without_output(mut cmd: Command,
+#[case] args: &[&str]) {
+  cmd.arg("gen").arg("examples/blocks.wasm")
+     .arg("build_the_model").arg("1").args(args)
+     .assert().failure().stdout("");
+}
+
+#[rstest]
+fn gen_closed_stdout(mut cmd: Command) {
+  let mut child = cmd.arg("gen").arg("examples/blocks.wasm")
+    .arg("build_the_model").arg("1")
+    .stdout(std::process::Stdio::piped())
+    .stderr(std::process::Stdio::piped())
+    .spawn().unwrap();
+  
+  // Closing the pipe before the model is generated, as `| head -c 0` would
+  drop(child.stdout.take());
+  let output = child.wait_with_output().unwrap();
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  
+  assert_eq!(output.status.code(), Some(ErrorCode::IO as i32));
+  assert!(stderr.starts_with("Error: Unable to write to stdout"), "{}",
+    stderr);
+}
+
+#[rstest]
+fn gen_external_buffers_with_format(mut cmd: Command) {
+  cmd.arg("gen").arg("examples/blocks.wasm")
+     .arg("build_the_model").arg("1").arg("--format").arg("gltf")
+     .arg("--external-buffers")
+     .assert().failure().stdout("");
+}
+
+#[rstest]
+fn gen_help_generator(mut cmd: Command) {
+  cmd.arg("gen").arg("examples/blocks.wasm")
+     .arg("build_the_model").arg("--help-generator")
+     .assert().code(ErrorCode::None as i32).stdout("\
+        build_the_model ( _a: i32 = 1 )\n\
+        \n\
+        A section of fortress wall battlement, with a red wall and a black \
+        top\n\
+        \n\
+        Parameters:\n\
+        \x20 _a: i32 = 1 (between 0 and 10)\n\
+        \x20     Not used yet\n");
+}
+
 #[rstest]
 fn gen_invalid_wasm(mut cmd: Command) {
   cmd.arg("inspect").arg("tests/invalid.wasm")
@@ -60,10 +250,12 @@ fn gen_missing_model_generator(mut cmd: Command) {
 }
 
 #[rstest]
-fn gen_parameter_count_low(mut cmd: Command) {
+fn gen_parameter_default(mut cmd: Command) {
+  let expected = std::fs::read("tests/build_the_model.glb").unwrap();
+  
   cmd.arg("gen").arg("examples/blocks.wasm")
      .arg("build_the_model")
-     .assert().code(ErrorCode::ParameterCount as i32).stdout("");
+     .assert().code(ErrorCode::None as i32).stdout(expected);
 }
 
 #[rstest]
@@ -84,12 +276,490 @@ fn gen_parameter_bad_type(mut cmd: Command, #[case] bad_paramter: String) {
      .assert().code(ErrorCode::ParameterType as i32).stdout("");
 }
 
-// emg::ErrorCode::WebAssemblyExection not tested - I don't know of a way to
-// make Web Assmbly execution fail
+#[rstest]
+fn gen_named_parameter(mut cmd: Command) {
+  let expected = std::fs::read("tests/build_the_model.glb").unwrap();
+  
+  cmd.arg("gen").arg("examples/blocks.wasm")
+     .arg("build_the_model").arg("--param").arg("_a=2")
+     .assert().code(ErrorCode::None as i32).stdout(expected);
+}
+
+#[rstest]
+#[case(&["--param", "b=1"])]
+#[case(&["1", "--param", "_a=1"])]
+#[case(&["--param", "_a=1", "--param", "_a=2"])]
+fn gen_named_parameter_bad_name(mut cmd: Command, #[case] args: &[&str]) {
+  cmd.arg("gen").arg("examples/blocks.wasm")
+     .arg("build_the_model").args(args)
+     .assert().code(ErrorCode::ParameterCount as i32).stdout("");
+}
+
+#[rstest]
+#[case("_a")]
+#[case("_a=a")]
+fn gen_named_parameter_bad_type(mut cmd: Command, #[case] pair: String) {
+  cmd.arg("gen").arg("examples/blocks.wasm")
+     .arg("build_the_model").arg("--param").arg(pair)
+     .assert().code(ErrorCode::ParameterType as i32).stdout("");
+}
+
+/// Expected values are the block's color, metallic factor, roughness factor,
+/// number of triangle indices (30 with an open top), and node name
+#[rstest]
+#[case(&[], 0x808080, 0.0, 0.9, 36, "Block")]
+#[case(&["#ff8800", "Metal", "true"], 0xff8800, 1.0, 0.3, 30, "Block")]
+#[case(&["ff8800", "Glossy", "false", "Sign"], 0xff8800, 0.0, 0.2, 36,
+  "Sign")]
+#[case(&["--param", "finish=Glossy", "--param", "open_top=true"], 0x808080,
+  0.0, 0.2, 30, "Block")]
+#[case(&["--param", "name=Welcome to the fortress ⚔"], 0x808080, 0.0, 0.9,
+  36, "Welcome to the fortress ⚔")]
+fn gen_parameter_types(mut cmd: Command, #[case] args: &[&str],
+#[case] color: u32, #[case] metallic: f64, #[case] roughness: f64,
+#[case] indices: u64, #[case] name: &str) {
+  let output = cmd.arg("gen").arg("examples/blocks.wasm")
+    .arg("block").args(args).arg("--format").arg("gltf")
+    .assert().code(ErrorCode::None as i32).get_output().stdout.clone();
+  let gltf: serde_json::Value = serde_json::from_slice(&output).unwrap();
+  
+  let material = &gltf["materials"][0]["pbrMetallicRoughness"];
+  let channel = |shift: u32| ((color >> shift) & 0xff) as f64/255.0;
+  assert_eq!(material["baseColorFactor"], serde_json::json!([channel(16),
+    channel(8), channel(0), 1.0]));
+  // GLTF leaves out factors that are at their default, which is 1.0
+  assert_eq!(material["metallicFactor"].as_f64().unwrap_or(1.0), metallic);
+  assert_eq!(material["roughnessFactor"].as_f64().unwrap_or(1.0), roughness);
+  
+  assert_eq!(gltf["accessors"][1]["count"], indices);
+  assert_eq!(gltf["nodes"][0]["name"], name);
+}
+
+#[rstest]
+#[case(&["red"])]
+#[case(&["#ff88"])]
+#[case(&["+ff880"])]
+#[case(&["#ff8800", "Wood"])]
+#[case(&["#ff8800", "Metal", "1"])]
+fn gen_parameter_types_bad_type(mut cmd: Command, #[case] args: &[&str]) {
+  cmd.arg("gen").arg("examples/blocks.wasm")
+     .arg("block").args(args)
+     .assert().code(ErrorCode::ParameterType as i32).stdout("");
+}
+
+#[rstest]
+#[case("-1")]
+#[case("11")]
+fn gen_parameter_out_of_range(mut cmd: Command, #[case] bad_paramter: String) {
+  cmd.arg("gen").arg("examples/blocks.wasm")
+     .arg("build_the_model").arg(bad_paramter)
+     .assert().code(ErrorCode::ParameterOutOfRange as i32).stdout("");
+}
+
+#[rstest]
+fn gen_generator_error_message(mut cmd: Command) {
+  cmd.arg("gen").arg("examples/blocks.wasm")
+     .arg("block").arg("--param").arg("name=")
+     .assert().code(ErrorCode::Generation as i32).stdout("")
+     .stderr("Error: model generation returned error code: 2 (Generation): \
+        block name cannot be empty\n");
+}
+
+#[rstest]
+fn gen_log(mut cmd: Command) {
+  cmd.arg("gen").arg("examples/blocks.wasm")
+     .arg("block").arg("--param").arg("name=Sign")
+     .assert().code(ErrorCode::None as i32).stderr("");
+}
+
+#[rstest]
+fn gen_log_verbose(mut cmd: Command) {
+  let output = cmd.arg("gen").arg("examples/blocks.wasm")
+    .arg("block").arg("--param").arg("name=Sign").arg("--verbose")
+    .assert().code(ErrorCode::None as i32).get_output().clone();
+  
+  assert!(String::from_utf8_lossy(&output.stderr)
+    .contains("[Debug] Building block `Sign`\n"));
+}
+
+#[rstest]
+fn gen_limits_sunny_day(mut cmd: Command) {
+  let expected = std::fs::read("tests/build_the_model.glb").unwrap();
+  
+  let output = cmd.arg("gen").arg("examples/blocks.wasm")
+    .arg("build_the_model").arg("1").arg("--timeout").arg("10000")
+    .arg("--fuel").arg("1000000000").arg("--verbose")
+    .assert().code(ErrorCode::None as i32).stdout(expected).get_output()
+    .clone();
+  
+  assert!(String::from_utf8_lossy(&output.stderr).contains("Fuel consumed: "));
+}
+
+#[rstest]
+fn gen_timeout(mut cmd: Command) {
+  cmd.arg("gen").arg("tests/infinite_loop.wasm")
+     .arg("infinite_loop").arg("--timeout").arg("100")
+     .assert().code(ErrorCode::WebAssemblyExecution as i32).stdout("")
+     .stderr("Error: model generator exceeded the time limit (100 ms)\n");
+}
+
+#[rstest]
+fn gen_fuel(mut cmd: Command) {
+  cmd.arg("gen").arg("tests/infinite_loop.wasm")
+     .arg("infinite_loop").arg("--fuel").arg("1000")
+     .assert().code(ErrorCode::WebAssemblyExecution as i32).stdout("")
+     .stderr("Error: model generator ran out of fuel (limit: 1000)\n");
+}
+
+#[rstest]
+fn gen_max_memory(mut cmd: Command) {
+  cmd.arg("gen").arg("tests/memory_hog.wasm")
+     .arg("memory_hog").arg("--max-memory").arg("1000000")
+     .assert().code(ErrorCode::WebAssemblyExecution as i32).stdout("")
+     .stderr("Error: model generator exceeded the memory limit (1000000 \
+        bytes)\n");
+}
+
+#[rstest]
+fn gen_stats(mut cmd: Command) {
+  let output = cmd.arg("gen").arg("examples/blocks.wasm")
+    .arg("build_the_model").arg("1").arg("--stats")
+    .assert().code(ErrorCode::None as i32).get_output().clone();
+  
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert!(stderr.contains("Generation time: "));
+  assert!(stderr.contains("Peak memory: "));
+  assert!(stderr.contains("Final memory: "));
+  assert!(stderr.contains("Output .glb size: 1612 bytes\n"));
+}
+
+#[rstest]
+fn gen_stats_memory(mut cmd: Command) {
+  let output = cmd.arg("gen").arg("tests/memory_hog.wasm")
+    .arg("memory_hog").arg("--stats")
+    .assert().get_output().clone();
+  
+  // 1 initial page and 100 grown pages, at 64 KiB each
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert!(stderr.contains("Peak memory: 6619136 bytes\n\
+    Final memory: 6619136 bytes\n"));
+}
+
+#[rstest]
+fn gen_cache(#[from(cmd)] mut first: Command, #[from(cmd)] mut second: Command)
+{
+  let cache = batch_dir("emg-test-output-cache");
+  let expected = std::fs::read("tests/build_the_model.gltf").unwrap();
+  
+  first.arg("gen").arg("examples/blocks.wasm")
+       .arg("build_the_model").arg("1").arg("--format").arg("gltf")
+       .arg("--cache-dir").arg(&cache).arg("--stats")
+       .assert().code(ErrorCode::None as i32).stdout(expected.clone())
+       .stderr(predicates::str::contains("Generation time: "));
+  
+  second.arg("gen").arg("examples/blocks.wasm")
+        .arg("build_the_model").arg("1").arg("--format").arg("gltf")
+        .arg("--cache-dir").arg(&cache).arg("--stats")
+        .assert().code(ErrorCode::None as i32).stdout(expected)
+        .stderr("Output read from cache\n");
+}
+
+#[rstest]
+#[case(&["--format", "gltf"])]
+#[case(&["--param", "name=Other"])]
+#[case(&["--no-cache"])]
+fn gen_cache_miss(#[from(cmd)] mut first: Command,
+#[from(cmd)] mut second: Command, #[case] args: &[&str]) {
+  let cache = batch_dir(&format!("emg-test-output-cache-miss-{}",
+    args.join("")));
+  
+  first.arg("gen").arg("examples/blocks.wasm").arg("block")
+       .arg("--cache-dir").arg(&cache)
+       .assert().code(ErrorCode::None as i32);
+  
+  second.arg("gen").arg("examples/blocks.wasm").arg("block")
+        .arg("--cache-dir").arg(&cache).args(args)
+        .arg("--stats")
+        .assert().code(ErrorCode::None as i32)
+        .stderr(predicates::str::contains("Generation time: "));
+}
+
+#[rstest]
+fn gen_cache_external_buffers(#[from(cmd)] mut first: Command,
+#[from(cmd)] mut second: Command) {
+  let cache = batch_dir("emg-test-output-cache-external-buffers");
+  let output = std::env::temp_dir().join("emg-test-cache-external.gltf");
+  let bin = std::env::temp_dir().join("emg-test-cache-external.bin");
+  
+  first.arg("gen").arg("examples/blocks.wasm").arg("build_the_model")
+       .arg("1").arg("-o").arg(&output).arg("--external-buffers")
+       .arg("--cache-dir").arg(&cache)
+       .assert().code(ErrorCode::None as i32);
+  let expected = (std::fs::read(&output).unwrap(),
+    std::fs::read(&bin).unwrap());
+  std::fs::remove_file(&output).unwrap();
+  std::fs::remove_file(&bin).unwrap();
+  
+  second.arg("gen").arg("examples/blocks.wasm").arg("build_the_model")
+        .arg("1").arg("-o").arg(&output).arg("--external-buffers")
+        .arg("--cache-dir").arg(&cache).arg("--stats")
+        .assert().code(ErrorCode::None as i32)
+        .stderr("Output read from cache\n");
+  assert_eq!((std::fs::read(&output).unwrap(), std::fs::read(&bin).unwrap()),
+    expected);
+}
 
 // emg::ErrorCode::OutputNotGLB not tested - I don't want to make a .wasm for
 // it right now
 
+/////////////////////
+// Tests for batch //
+/////////////////////
+
+/// Fresh output directory for a batch test
+fn batch_dir(name: &str) -> std::path::PathBuf {
+  let dir = std::env::temp_dir().join(name);
+  let _ = std::fs::remove_dir_all(&dir);
+  dir
+}
+
+fn read_manifest(dir: &std::path::Path) -> serde_json::Value {
+  serde_json::from_slice(&std::fs::read(dir.join("manifest.json")).unwrap())
+    .unwrap()
+}
+
+#[rstest]
+fn batch_sunny_day(mut cmd: Command) {
+  let expected = std::fs::read("tests/build_the_model.glb").unwrap();
+  let dir = batch_dir("emg-test-batch");
+  
+  cmd.arg("batch").arg("examples/blocks.wasm").arg("build_the_model")
+     .arg("--sweep").arg("_a=1..3:1").arg("--out-dir").arg(&dir)
+     .assert().code(ErrorCode::None as i32).stdout("");
+  
+  let manifest = read_manifest(&dir);
+  assert_eq!(manifest["generator"], "build_the_model");
+  
+  let models = manifest["models"].as_array().unwrap();
+  assert_eq!(models.len(), 3);
+  for (i, model) in models.iter().enumerate() {
+    let file = format!("build_the_model-{}.glb", i + 1);
+    assert_eq!(model["file"], file.as_str());
+    assert_eq!(model["parameters"]["_a"], (i + 1).to_string());
+    assert_eq!(std::fs::read(dir.join(file)).unwrap(), expected);
+  }
+}
+
+#[rstest]
+fn batch_multiple_sweeps(mut cmd: Command) {
+  let dir = batch_dir("emg-test-batch-multiple");
+  
+  cmd.arg("batch").arg("examples/blocks.wasm").arg("block")
+     .arg("--sweep").arg("finish=Matte,Metal")
+     .arg("--sweep").arg("open_top=false,true")
+     .arg("--param").arg("color=#ff0000")
+     .arg("--name").arg("{index}-{finish}-{open_top}")
+     .arg("--format").arg("gltf").arg("--out-dir").arg(&dir)
+     .assert().code(ErrorCode::None as i32).stdout("");
+  
+  let manifest = read_manifest(&dir);
+  let files: Vec<&str> = manifest["models"].as_array().unwrap().iter()
+    .map(|model| model["file"].as_str().unwrap()).collect();
+  assert_eq!(files, ["0-Matte-false.gltf", "1-Matte-true.gltf",
+    "2-Metal-false.gltf", "3-Metal-true.gltf"]);
+  
+  for file in files {
+    assert!(dir.join(file).exists());
+  }
+  
+  assert_eq!(manifest["models"][2]["parameters"], serde_json::json!({
+    "color": "#ff0000",
+    "finish": "Metal",
+    "open_top": "false",
+    "name": "Block",
+  }));
+}
+
+#[rstest]
+fn batch_generator_error(mut cmd: Command) {
+  let dir = batch_dir("emg-test-batch-error");
+  
+  // The second model fails, but the rest of the batch still runs
+  cmd.arg("batch").arg("examples/blocks.wasm").arg("block")
+     .arg("--sweep").arg("name=a,,b").arg("--out-dir").arg(&dir)
+     .assert().code(ErrorCode::Generation as i32).stdout("");
+  
+  let manifest = read_manifest(&dir);
+  let models = manifest["models"].as_array().unwrap();
+  assert_eq!(models.len(), 3);
+  assert!(models[0].get("error").is_none());
+  assert!(models[1]["error"].as_str().unwrap()
+    .ends_with("block name cannot be empty"));
+  assert!(models[2].get("error").is_none());
+  
+  assert!(dir.join("block-a.glb").exists());
+  assert!(!dir.join("block-.glb").exists());
+  assert!(dir.join("block-b.glb").exists());
+}
+
+#[rstest]
+#[case("_a")]
+#[case("_a=1..x")]
+#[case("_a=3..1")]
+#[case("_a=1..3:0")]
+fn batch_bad_sweep(mut cmd: Command, #[case] sweep: &str) {
+  cmd.arg("batch").arg("examples/blocks.wasm").arg("build_the_model")
+     .arg("--sweep").arg(sweep)
+     .arg("--out-dir").arg(batch_dir("emg-test-batch-bad-sweep"))
+     .assert().code(ErrorCode::ParameterType as i32).stdout("");
+}
+
+#[rstest]
+fn batch_name_collision(mut cmd: Command) {
+  let dir = batch_dir("emg-test-batch-collision");
+  
+  cmd.arg("batch").arg("examples/blocks.wasm").arg("build_the_model")
+     .arg("--sweep").arg("_a=1,2").arg("--name").arg("same")
+     .arg("--out-dir").arg(&dir)
+     .assert().code(ErrorCode::IO as i32).stdout("");
+  
+  // Nothing is generated if the names collide
+  assert!(!dir.exists());
+}
+
+/////////////////////
+// Tests for build //
+/////////////////////
+
+/// Writes a build file to a fresh temporary directory. `{wasm}` is replaced
+/// with the directory containing test .wasm files
+fn build_file(name: &str, contents: &str) -> std::path::PathBuf {
+  let dir = batch_dir(name);
+  std::fs::create_dir(&dir).unwrap();
+  
+  let wasm_dir = std::env::current_dir().unwrap();
+  let contents = contents.replace("{wasm}", wasm_dir.to_str().unwrap());
+  std::fs::write(dir.join("emg.toml"), contents).unwrap();
+  
+  dir
+}
+
+#[rstest]
+fn build_sunny_day(mut cmd: Command) {
+  let dir = build_file("emg-test-build", r##"
+    [[model]]
+    wasm = "{wasm}/examples/blocks.wasm"
+    generator = "build_the_model"
+    output = "wall.glb"
+    parameters = [1]
+    
+    [[model]]
+    wasm = "{wasm}/examples/blocks.wasm"
+    generator = "block"
+    output = "blocks/red.gltf"
+    parameters = { color = "#ff0000", open_top = true }
+    
+    [[model]]
+    wasm = "{wasm}/examples/blocks.wasm"
+    generator = "block"
+    output = "blocks/plain.gltf"
+    format = "gltf-separate"
+  "##);
+  
+  cmd.arg("build").arg(dir.join("emg.toml")).arg("--jobs").arg("2")
+     .assert().code(ErrorCode::None as i32).stdout("");
+  
+  assert_eq!(std::fs::read(dir.join("wall.glb")).unwrap(),
+    std::fs::read("tests/build_the_model.glb").unwrap());
+  let red = std::fs::read(dir.join("blocks/red.gltf")).unwrap();
+  assert_eq!(red, std::fs::read("tests/red_block.gltf").unwrap());
+  let red: serde_json::Value = serde_json::from_slice(&red).unwrap();
+  assert_eq!(red["materials"][0]["pbrMetallicRoughness"]["baseColorFactor"],
+    serde_json::json!([1.0, 0.0, 0.0, 1.0]));
+  assert!(dir.join("blocks/plain.gltf").exists());
+  assert!(dir.join("blocks/plain.bin").exists());
+}
+
+#[rstest]
+fn build_generator_error(mut cmd: Command) {
+  let dir = build_file("emg-test-build-error", r#"
+    [[model]]
+    wasm = "{wasm}/examples/blocks.wasm"
+    generator = "block"
+    output = "unnamed.glb"
+    parameters = { name = "" }
+    
+    [[model]]
+    wasm = "{wasm}/examples/blocks.wasm"
+    generator = "block"
+    output = "named.glb"
+  "#);
+  
+  // The failing model does not stop the other
+  cmd.arg("build").arg(dir.join("emg.toml"))
+     .assert().code(ErrorCode::Generation as i32).stdout("");
+  
+  assert!(!dir.join("unnamed.glb").exists());
+  assert!(dir.join("named.glb").exists());
+}
+
+#[rstest]
+fn build_timeout(mut cmd: Command) {
+  let dir = build_file("emg-test-build-timeout", r#"
+    [[model]]
+    wasm = "{wasm}/tests/infinite_loop.wasm"
+    generator = "infinite_loop"
+    output = "loop.glb"
+    
+    [[model]]
+    wasm = "{wasm}/examples/blocks.wasm"
+    generator = "build_the_model"
+    output = "wall.glb"
+    parameters = [1]
+  "#);
+  
+  // A timeout on one thread must not interrupt models on other threads
+  cmd.arg("build").arg(dir.join("emg.toml")).arg("--jobs").arg("2")
+     .arg("--timeout").arg("500")
+     .assert().code(ErrorCode::WebAssemblyExecution as i32).stdout("");
+  
+  assert!(dir.join("wall.glb").exists());
+}
+
+#[rstest]
+#[case("[[model]")]
+#[case("[[model]]\nwasm = \"a.wasm\"\ngenerator = \"a\"\noutput = \"a.glb\"\n\
+  colour = 1")]
+#[case("[[model]]\nwasm = \"{wasm}/examples/blocks.wasm\"\n\
+  generator = \"block\"\noutput = \"a.glb\"\nformat = \"obj\"")]
+#[case("[[model]]\nwasm = \"{wasm}/examples/blocks.wasm\"\n\
+  generator = \"block\"\noutput = \"a.glb\"\nparameters = [[1]]")]
+fn build_bad_file(mut cmd: Command, #[case] contents: &str) {
+  let dir = build_file("emg-test-build-bad-file", contents);
+  
+  cmd.arg("build").arg(dir.join("emg.toml"))
+     .assert().code(ErrorCode::BuildFile as i32).stdout("");
+}
+
+#[rstest]
+fn build_missing_model_generator(mut cmd: Command) {
+  let dir = build_file("emg-test-build-missing-generator", r#"
+    [[model]]
+    wasm = "{wasm}/examples/blocks.wasm"
+    generator = "nonexistent"
+    output = "a.glb"
+  "#);
+  
+  cmd.arg("build").arg(dir.join("emg.toml"))
+     .assert().code(ErrorCode::ModelGeneratorNotFound as i32).stdout("");
+}
+
+#[rstest]
+fn build_missing_file(mut cmd: Command) {
+  cmd.arg("build").arg("tests/nonexistent.toml")
+     .assert().code(ErrorCode::IO as i32).stdout("");
+}
+
 ///////////////////////
 // Tests for inspect //
 ///////////////////////
@@ -99,15 +769,312 @@ fn inspect_sunny_day(mut cmd: Command) {
   cmd.arg("inspect").arg("examples/blocks.wasm")
      .assert().code(ErrorCode::None as i32).stdout("")
      .stderr("Model generators found:\n\
-        \tbuild_the_model ( i32 )\n");
+        \tblock ( color: color = #808080, finish: Finish = Matte, open_top: \
+        bool = false, name: string = \"Block\" )\n\
+        \t\tA single block, in any color\n\
+        \tbuild_the_model ( _a: i32 = 1 )\n\
+        \t\tA section of fortress wall battlement, with a red wall and a \
+        black top\n");
+}
+
+#[rstest]
+fn inspect_json_sunny_day(mut cmd: Command) {
+  let output = cmd.arg("inspect").arg("examples/blocks.wasm")
+    .arg("--format").arg("json")
+    .assert().code(ErrorCode::None as i32).stderr("").get_output().clone();
+  
+  let description: serde_json::Value = serde_json::from_slice(&output.stdout)
+    .unwrap();
+  
+  assert_eq!(description["module"], "examples/blocks.wasm");
+  assert_eq!(description["size"],
+    std::fs::metadata("examples/blocks.wasm").unwrap().len());
+  assert_eq!(description["generators"][0]["parameters"][1],
+    serde_json::json!({
+      "name": "finish",
+      "type": "enum",
+      "doc": "Surface finish of the block",
+      "enum": "Finish",
+      "variants": ["Matte", "Glossy", "Metal"],
+      "default": "Matte",
+    }));
+  assert_eq!(description["generators"][1], serde_json::json!({
+    "name": "build_the_model",
+    "doc": "A section of fortress wall battlement, with a red wall and a \
+      black top",
+    "parameters": [{
+      "name": "_a",
+      "type": "i32",
+      "doc": "Not used yet",
+      "default": 1,
+      "min": 0,
+      "max": 10,
+    }],
+  }));
+  assert_eq!(description["accessors"], serde_json::json!([
+    "model_pointer", "model_size",
+  ]));
+}
+
+#[rstest]
+fn gen_no_cache_directory(#[from(cmd)] mut first: Command,
+#[from(cmd)] mut second: Command) {
+  // Without a user cache directory, emg must not fall back to a shared one
+  let temp = batch_dir("emg-test-no-cache-directory");
+  std::fs::create_dir(&temp).unwrap();
+  
+  for cmd in [&mut first, &mut second] {
+    cmd.env_remove("EMG_CACHE_DIR").env_remove("XDG_CACHE_HOME")
+       .env_remove("HOME").env("TMPDIR", &temp)
+       .arg("gen").arg("examples/blocks.wasm").arg("build_the_model").arg("1")
+       .arg("--stats");
+  }
+  
+  first.assert().code(ErrorCode::None as i32);
+  second.assert().code(ErrorCode::None as i32)
+        .stderr(predicates::str::contains("Generation time: "));
+  
+  assert_eq!(std::fs::read_dir(&temp).unwrap().count(), 0);
+}
+
+#[rstest]
+fn inspect_module_cache(#[from(cmd)] mut first: Command,
+#[from(cmd)] mut second: Command) {
+  let cache = batch_dir("emg-test-module-cache");
+  
+  let expected = first.arg("inspect").arg("examples/blocks.wasm")
+    .arg("--format").arg("json").arg("--cache-dir").arg(&cache)
+    .assert().code(ErrorCode::None as i32).get_output().stdout.clone();
+  
+  let modules: Vec<_> = std::fs::read_dir(cache.join("modules")).unwrap()
+    .map(|entry| entry.unwrap().path()).collect();
+  assert_eq!(modules.len(), 1);
+  
+  // An unusable cached module is recompiled and replaced
+  std::fs::write(&modules[0], b"not a compiled module").unwrap();
+  
+  second.arg("inspect").arg("examples/blocks.wasm")
+        .arg("--format").arg("json").arg("--cache-dir").arg(&cache)
+        .assert().code(ErrorCode::None as i32).stdout(expected);
+  
+  assert_ne!(std::fs::read(&modules[0]).unwrap(), b"not a compiled module");
+}
+
+//////////////////////////
+// Tests for precompile //
+//////////////////////////
+
+#[rstest]
+fn precompile_sunny_day(#[from(cmd)] mut precompile: Command,
+#[from(cmd)] mut gen: Command, #[from(cmd)] mut help: Command) {
+  let cwasm = std::env::temp_dir().join("emg-test-precompile.cwasm");
+  let _ = std::fs::remove_file(&cwasm);
+  
+  precompile.arg("precompile").arg("examples/blocks.wasm")
+            .arg("--output").arg(&cwasm)
+            .assert().code(ErrorCode::None as i32).stdout("");
+  
+  gen.arg("gen").arg(&cwasm).arg("build_the_model").arg("1")
+     .assert().code(ErrorCode::None as i32)
+     .stdout(std::fs::read("tests/build_the_model.glb").unwrap());
+  
+  // Metadata from the .wasm file is kept
+  help.arg("gen").arg(&cwasm).arg("build_the_model").arg("--help-generator")
+      .assert().code(ErrorCode::None as i32)
+      .stdout(predicates::str::starts_with("build_the_model ( _a: i32 = 1 )"));
+}
+
+#[rstest]
+fn precompile_default_output(mut cmd: Command) {
+  let dir = batch_dir("emg-test-precompile-default-output");
+  std::fs::create_dir(&dir).unwrap();
+  std::fs::copy("examples/blocks.wasm", dir.join("blocks.wasm")).unwrap();
+  
+  cmd.arg("precompile").arg(dir.join("blocks.wasm"))
+     .assert().code(ErrorCode::None as i32).stdout("");
+  
+  assert!(dir.join("blocks.cwasm").exists());
+}
+
+#[rstest]
+fn precompile_cwasm(#[from(cmd)] mut first: Command,
+#[from(cmd)] mut second: Command) {
+  let cwasm = std::env::temp_dir().join("emg-test-precompile-twice.cwasm");
+  
+  first.arg("precompile").arg("examples/blocks.wasm").arg("-o").arg(&cwasm)
+       .assert().code(ErrorCode::None as i32);
+  
+  second.arg("precompile").arg(&cwasm)
+        .assert().code(ErrorCode::WebAssemblyCompile as i32).stdout("");
+}
+
+#[rstest]
+fn precompile_invalid_wasm(mut cmd: Command) {
+  cmd.arg("precompile").arg("tests/invalid.wasm")
+     .arg("-o").arg(std::env::temp_dir().join("emg-test-invalid.cwasm"))
+     .assert().code(ErrorCode::WebAssemblyCompile as i32).stdout("");
+}
+
+#[rstest]
+fn gen_cwasm_named_wasm(#[from(cmd)] mut precompile: Command,
+#[from(cmd)] mut gen: Command) {
+  let wasm = std::env::temp_dir().join("emg-test-precompiled.wasm");
+  
+  precompile.arg("precompile").arg("examples/blocks.wasm").arg("-o").arg(&wasm)
+            .assert().code(ErrorCode::None as i32);
+  
+  // Only files named .cwasm may contain machine code
+  gen.arg("gen").arg(&wasm).arg("build_the_model").arg("1")
+     .assert().code(ErrorCode::WebAssemblyCompile as i32).stdout("");
+}
+
+#[rstest]
+fn gen_malformed_cwasm(mut cmd: Command) {
+  let cwasm = std::env::temp_dir().join("emg-test-malformed.cwasm");
+  std::fs::write(&cwasm, b"\0emgcwasm\x10\0\0\0{}").unwrap();
+  
+  cmd.arg("gen").arg(&cwasm).arg("build_the_model").arg("1")
+     .assert().code(ErrorCode::WebAssemblyCompile as i32).stdout("");
 }
 
 /////////////////////
 // Tests for serve //
 /////////////////////
 
+/// Starts a server for examples/blocks.wasm, sends it a GET request (retrying
+/// while the server starts up), and stops it. Returns the raw response,
+/// including headers
+fn serve_and_get(cmd: Command, port: u16, path: &str) -> Vec<u8> {
+  serve_args_and_get(cmd, &["examples/blocks.wasm"], port, path)
+}
+
+/// Same as serve_and_get(), but with the given arguments to serve
+fn serve_args_and_get(mut cmd: Command, args: &[&str], port: u16, path: &str)
+-> Vec<u8> {
+  let mut server = cmd.arg("serve").args(args)
+    .arg("--port").arg(port.to_string()).spawn().unwrap();
+  
+  // The server compiles the .wasm file before it starts listening, which can
+  // take several seconds while other tests are running
+  let mut response = Vec::new();
+  for _ in 0..300 {
+    match std::net::TcpStream::connect(("127.0.0.1", port)) {
+      Ok(mut stream) => {
+        write!(stream, "GET {} HTTP/1.0\r\n\r\n", path).unwrap();
+        stream.read_to_end(&mut response).unwrap();
+        break;
+      },
+      Err(_) => std::thread::sleep(std::time::Duration::from_millis(100)),
+    }
+  }
+  
+  server.kill().unwrap();
+  server.wait().unwrap();
+  
+  assert!(!response.is_empty(), "Unable to connect to test server");
+  response
+}
+
 #[rstest]
-fn serve_not_implemented(mut cmd: Command) {
-  cmd.arg("serve").arg("examples/blocks.wasm")
-     .assert().code(ErrorCode::NotImplemented as i32).stdout("");
+fn serve_sunny_day(cmd: Command) {
+  let expected = std::fs::read("tests/build_the_model.glb").unwrap();
+  
+  // Browsers encode @ as %40
+  let response = serve_and_get(cmd, 18301, "/gen/build_the_model?%40=1");
+  
+  assert!(response.starts_with(b"HTTP/1.0 200"));
+  assert!(response.ends_with(&expected));
+}
+
+#[rstest]
+fn serve_named_parameter(cmd: Command) {
+  let expected = std::fs::read("tests/build_the_model.glb").unwrap();
+  
+  let response = serve_and_get(cmd, 18303, "/gen/build_the_model?_a=2");
+  
+  assert!(response.starts_with(b"HTTP/1.0 200"));
+  assert!(response.ends_with(&expected));
+}
+
+#[rstest]
+fn serve_p_is_a_named_parameter(cmd: Command) {
+  let response = serve_and_get(cmd, 18305, "/gen/build_the_model?p=1");
+  
+  let response = String::from_utf8_lossy(&response);
+  assert!(response.starts_with("HTTP/1.0 400"));
+  assert!(response.contains("has no parameter named `p`"));
+}
+
+#[rstest]
+fn serve_log(cmd: Command) {
+  use base64::Engine;
+  
+  let response = serve_and_get(cmd, 18304, "/gen/block?name=Sign");
+  
+  let response = String::from_utf8_lossy(&response);
+  let header = response.lines()
+    .find_map(|line| line.strip_prefix("X-EMG-Log: "))
+    .expect("Response should include logs");
+  let logs: serde_json::Value = serde_json::from_slice(
+    &base64::engine::general_purpose::STANDARD.decode(header).unwrap())
+    .unwrap();
+  
+  assert_eq!(logs, serde_json::json!([{
+    "level": "Debug",
+    "message": "Building block `Sign`",
+  }]));
+}
+
+#[rstest]
+fn serve_log_flood(cmd: Command) {
+  use base64::Engine;
+  
+  let response = serve_args_and_get(cmd, &["tests/log_flood.wasm"], 18307,
+    "/gen/log_flood");
+  
+  let response = String::from_utf8_lossy(&response);
+  let header = response.lines()
+    .find_map(|line| line.strip_prefix("X-EMG-Log: "))
+    .expect("Response should include logs");
+  assert!(header.len() <= 8 * 1024);
+  
+  let logs: Vec<serde_json::Value> = serde_json::from_slice(
+    &base64::engine::general_purpose::STANDARD.decode(header).unwrap())
+    .unwrap();
+  let (last, sent) = logs.split_last().unwrap();
+  
+  assert!(!sent.is_empty());
+  assert!(sent.iter().all(|log| log["message"] == "Flooding the log"));
+  assert_eq!(last["level"], "Warn");
+  assert_eq!(last["message"].as_str().unwrap().split_once(' ').unwrap().0,
+    (10000 - sent.len()).to_string());
+}
+
+#[rstest]
+fn serve_parameter_bad_type(cmd: Command) {
+  let response = serve_and_get(cmd, 18302, "/gen/build_the_model?@=a");
+  
+  let response = String::from_utf8_lossy(&response);
+  assert!(response.starts_with("HTTP/1.0 400"));
+  assert!(response.contains(&format!("X-EMG-Error-Code: {}",
+    ErrorCode::ParameterType as i32)));
+}
+
+#[rstest]
+fn serve_timeout(cmd: Command) {
+  let response = serve_args_and_get(cmd, &["tests/infinite_loop.wasm",
+    "--timeout", "100"], 18306, "/gen/infinite_loop");
+  
+  let response = String::from_utf8_lossy(&response);
+  assert!(response.starts_with("HTTP/1.0 500"));
+  assert!(response.contains(&format!("X-EMG-Error-Code: {}",
+    ErrorCode::WebAssemblyExecution as i32)));
+  assert!(response.ends_with(
+    "Error: model generator exceeded the time limit (100 ms)\n"));
+}
+
+#[rstest]
+fn serve_invalid_wasm(mut cmd: Command) {
+  cmd.arg("serve").arg("tests/invalid.wasm")
+     .assert().code(ErrorCode::WebAssemblyCompile as i32).stdout("");
 }
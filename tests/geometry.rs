@@ -0,0 +1,537 @@
+use std::f64::consts::PI;
+
+use rstest::rstest;
+use emg::{Geometry, Matrix4, SelectionType, UnitQuaternion, V3};
+
+/// Geometry with one vertex and no triangles
+fn point(x: f64, y: f64, z: f64) -> Geometry {
+  Geometry {
+    vertices: vec![V3::new(x, y, z)],
+    triangles: Vec::new(),
+    selection: Vec::new(),
+    selection_type: SelectionType::VERTICES,
+  }
+}
+
+fn assert_vertices_eq(geometry: &Geometry, expected: &[V3<f64>]) {
+  assert_eq!(geometry.vertices.len(), expected.len());
+  
+  for (actual, expected) in geometry.vertices.iter().zip(expected) {
+    assert!((actual - expected).norm() < 1e-9, "expected {:?}, got {:?}",
+      expected, actual);
+  }
+}
+
+/////////////////////////////
+// Tests for rotations and //
+// matrix transforms       //
+/////////////////////////////
+
+#[rstest]
+#[case(PI/2.0, 0.0, 0.0, V3::new(0.0, 0.0, 1.0))]
+#[case(0.0, PI/2.0, 0.0, V3::new(0.0, 1.0, 0.0))]
+#[case(0.0, 0.0, PI/2.0, V3::new(-1.0, 0.0, 0.0))]
+// X is applied first, so Y then moves the result
+#[case(PI/2.0, PI/2.0, 0.0, V3::new(1.0, 0.0, 0.0))]
+fn r_euler(#[case] x: f64, #[case] y: f64, #[case] z: f64,
+#[case] expected: V3<f64>) {
+  let mut geometry = point(0.0, 1.0, 0.0);
+  geometry.r(x, y, z);
+  
+  assert_vertices_eq(&geometry, &[expected]);
+}
+
+#[rstest]
+fn r_axis_unnormalized() {
+  let mut geometry = point(1.0, 0.0, 0.0);
+  geometry.r_axis(V3::new(0.0, 0.0, 2.0), PI/2.0);
+  
+  assert_vertices_eq(&geometry, &[V3::new(0.0, 1.0, 0.0)]);
+}
+
+#[rstest]
+fn r_axis_zero() {
+  let mut geometry = point(1.0, 2.0, 3.0);
+  geometry.r_axis(V3::zeros(), PI/2.0);
+  
+  assert_vertices_eq(&geometry, &[V3::new(1.0, 2.0, 3.0)]);
+}
+
+#[rstest]
+fn r_quaternion_matches_euler() {
+  let mut by_euler = Geometry::cube();
+  by_euler.r(0.3, -1.2, 2.0);
+  
+  let mut by_quaternion = Geometry::cube();
+  by_quaternion.r_quaternion(&UnitQuaternion::from_euler_angles(0.3, -1.2,
+    2.0));
+  
+  assert_vertices_eq(&by_quaternion, &by_euler.vertices);
+}
+
+#[rstest]
+fn r_pivot() {
+  let mut geometry = point(2.0, 0.0, 0.0);
+  geometry.r_pivot(V3::new(1.0, 0.0, 0.0), 0.0, 0.0, PI);
+  
+  assert_vertices_eq(&geometry, &[V3::new(0.0, 0.0, 0.0)]);
+}
+
+#[rstest]
+fn s_pivot() {
+  let mut geometry = point(2.0, 0.0, 1.0);
+  geometry.s_pivot(V3::new(1.0, 0.0, 1.0), 3.0, 3.0, 3.0);
+  
+  assert_vertices_eq(&geometry, &[V3::new(4.0, 0.0, 1.0)]);
+}
+
+#[rstest]
+fn transform_translate_and_scale() {
+  let mut geometry = point(1.0, 1.0, 1.0);
+  geometry.transform(&(Matrix4::new_translation(&V3::new(1.0, 2.0, 3.0)) *
+    Matrix4::new_scaling(2.0)));
+  
+  assert_vertices_eq(&geometry, &[V3::new(3.0, 4.0, 5.0)]);
+}
+
+#[rstest]
+fn transform_matches_r() {
+  let mut by_r = Geometry::cube();
+  by_r.r(1.0, 0.5, -0.25);
+  
+  let mut by_matrix = Geometry::cube();
+  by_matrix.transform(&UnitQuaternion::from_euler_angles(1.0, 0.5, -0.25)
+    .to_homogeneous());
+  
+  assert_vertices_eq(&by_matrix, &by_r.vertices);
+}
+
+#[rstest]
+fn transforms_chain() {
+  let mut geometry = point(1.0, 0.0, 0.0);
+  geometry.r(0.0, 0.0, PI/2.0).t(0.0, 0.0, 1.0).s(2.0, 2.0, 2.0);
+  
+  assert_vertices_eq(&geometry, &[V3::new(0.0, 2.0, 2.0)]);
+}
+
+////////////////////////////////////////
+// Tests for selection-scoped changes //
+////////////////////////////////////////
+
+/// Vertices of Geometry::cube() that are on the top (+Z) face
+const CUBE_TOP: [usize; 4] = [1, 3, 5, 7];
+
+#[rstest]
+fn t_selected_vertices() {
+  let mut geometry = Geometry::cube();
+  geometry.select_vertices(V3::new(-1.0, -1.0, 1.0), V3::new(1.0, 1.0, 1.0));
+  geometry.t_selected(0.0, 0.0, 2.0);
+  
+  for (i, vertex) in geometry.vertices.iter().enumerate() {
+    let expected_z = if CUBE_TOP.contains(&i) { 3.0 } else { -1.0 };
+    assert_eq!(vertex.z, expected_z);
+  }
+}
+
+#[rstest]
+fn t_selected_triangles_moves_shared_vertices_once() {
+  let mut geometry = Geometry::cube();
+  geometry.select_triangles(V3::new(1.0, -1.0, -1.0), V3::new(1.0, 1.0, 1.0));
+  assert_eq!(geometry.selection.len(), 2);
+  
+  geometry.t_selected(1.0, 0.0, 0.0);
+  
+  let xs: Vec<f64> = geometry.vertices.iter().map(|vertex| vertex.x)
+    .collect();
+  assert_eq!(xs, [-1.0, -1.0, -1.0, -1.0, 2.0, 2.0, 2.0, 2.0]);
+}
+
+#[rstest]
+fn s_pivot_selected_tapers_top() {
+  let mut geometry = Geometry::cube();
+  geometry.select_vertices(V3::new(-1.0, -1.0, 1.0), V3::new(1.0, 1.0, 1.0));
+  geometry.s_pivot_selected(V3::new(0.0, 0.0, 1.0), 0.5, 0.5, 1.0);
+  
+  for (i, vertex) in geometry.vertices.iter().enumerate() {
+    let expected_width = if CUBE_TOP.contains(&i) { 0.5 } else { 1.0 };
+    assert_eq!(vertex.x.abs(), expected_width);
+    assert_eq!(vertex.y.abs(), expected_width);
+    assert_eq!(vertex.z.abs(), 1.0);
+  }
+}
+
+#[rstest]
+fn r_selected_matches_r_for_everything() {
+  let mut by_r = Geometry::cube();
+  by_r.r(0.1, 0.2, 0.3);
+  
+  let mut by_selection = Geometry::cube();
+  by_selection.select_vertices(V3::repeat(-1.0), V3::repeat(1.0));
+  by_selection.r_selected(0.1, 0.2, 0.3);
+  
+  assert_vertices_eq(&by_selection, &by_r.vertices);
+}
+
+#[rstest]
+fn r_pivot_selected() {
+  let mut geometry = point(2.0, 0.0, 0.0);
+  geometry.vertices.push(V3::new(5.0, 5.0, 5.0));
+  geometry.selection = vec![0];
+  geometry.r_pivot_selected(V3::new(1.0, 0.0, 0.0), 0.0, 0.0, PI);
+  
+  assert_vertices_eq(&geometry, &[V3::new(0.0, 0.0, 0.0),
+    V3::new(5.0, 5.0, 5.0)]);
+}
+
+#[rstest]
+fn transform_selected_empty_selection() {
+  let mut geometry = Geometry::cube();
+  geometry.transform_selected(&Matrix4::new_scaling(2.0));
+  
+  assert_vertices_eq(&geometry, &Geometry::cube().vertices);
+}
+
+///////////////////////
+// Tests for merging //
+///////////////////////
+
+#[rstest]
+fn merge_offsets_triangles() {
+  let mut geometry = Geometry::cube();
+  let mut other = Geometry::cube();
+  other.t(3.0, 0.0, 0.0);
+  
+  geometry.merge(&other);
+  
+  assert_eq!(geometry.vertices.len(), 16);
+  assert_eq!(geometry.triangles.len(), 24);
+  assert_eq!(geometry.vertices[8], V3::new(2.0, 1.0, -1.0));
+  assert_eq!(geometry.triangles[12], [9, 11, 13]);
+  assert_eq!(&geometry.triangles[..12], &Geometry::cube().triangles[..]);
+}
+
+#[rstest]
+fn merge_keeps_selection() {
+  let mut geometry = Geometry::cube();
+  geometry.select_vertices(V3::new(-1.0, -1.0, 1.0), V3::new(1.0, 1.0, 1.0));
+  geometry.merge(&Geometry::cube());
+  
+  assert_eq!(geometry.selection, [1, 3, 5, 7]);
+}
+
+#[rstest]
+fn from_parts_owned_and_borrowed() {
+  let parts: Vec<Geometry> = (0..3).map(|i| {
+    let mut part = Geometry::cube();
+    part.t(3.0*i as f64, 0.0, 0.0);
+    part
+  }).collect();
+  
+  let borrowed = Geometry::from_parts(&parts);
+  let owned = Geometry::from_parts(parts);
+  
+  assert_eq!(owned.vertices, borrowed.vertices);
+  assert_eq!(owned.triangles, borrowed.triangles);
+  assert_eq!(owned.vertices.len(), 24);
+  assert_eq!(owned.triangles.len(), 36);
+  assert_eq!(owned.triangles[35], [18, 20, 22]);
+}
+
+#[rstest]
+fn from_parts_empty() {
+  let geometry = Geometry::from_parts(Vec::<Geometry>::new());
+  
+  assert!(geometry.vertices.is_empty());
+  assert!(geometry.triangles.is_empty());
+}
+
+#[rstest]
+fn from_parts_packs_as_one_primitive() {
+  let mut gltf = emg::GLTF::new("");
+  Geometry::from_parts([Geometry::cube(), Geometry::cube()]).pack(&mut gltf);
+  
+  assert_eq!(gltf.accessors.len(), 2);
+  assert_eq!(gltf.accessors[0].count, 16);
+  assert_eq!(gltf.accessors[1].count, 72);
+}
+
+///////////////////////
+// Tests for welding //
+///////////////////////
+
+#[rstest]
+fn weld_touching_cubes() {
+  let mut right = Geometry::cube();
+  right.t(2.0, 0.0, 0.0);
+  let mut geometry = Geometry::from_parts([Geometry::cube(), right]);
+  
+  geometry.weld(1e-6);
+  
+  assert_eq!(geometry.vertices.len(), 12);
+  assert_eq!(geometry.triangles.len(), 24);
+  // The right cube's -X side now uses the left cube's +X vertices
+  assert_eq!(geometry.triangles[16], [4, 6, 5]);
+}
+
+#[rstest]
+#[case(1e-6, 1)]
+#[case(1e-8, 2)]
+#[case(0.0, 2)]
+fn weld_epsilon(#[case] epsilon: f64, #[case] expected_vertices: usize) {
+  let mut geometry = point(1.0, 2.0, 3.0);
+  geometry.vertices.push(V3::new(1.0, 2.0, 3.0 + 1e-7));
+  
+  geometry.weld(epsilon);
+  
+  assert_eq!(geometry.vertices.len(), expected_vertices);
+}
+
+#[rstest]
+fn weld_exact_with_zero_epsilon() {
+  let mut geometry = point(1.0, 2.0, 3.0);
+  geometry.vertices.push(V3::new(1.0, 2.0, 3.0));
+  
+  geometry.weld(0.0);
+  
+  assert_eq!(geometry.vertices.len(), 1);
+}
+
+#[rstest]
+fn weld_across_cell_boundary() {
+  let mut geometry = point(1.0 - 1e-4, 0.0, 0.0);
+  geometry.vertices.push(V3::new(1.0 + 1e-4, 0.0, 0.0));
+  
+  geometry.weld(1e-3);
+  
+  assert_vertices_eq(&geometry, &[V3::new(1.0 - 1e-4, 0.0, 0.0)]);
+}
+
+#[rstest]
+#[case(1e7, 1e-12)]
+#[case(1e30, 0.0)]
+#[case(-1e300, 1e-300)]
+fn weld_large_coordinates(#[case] x: f64, #[case] epsilon: f64) {
+  let mut geometry = point(x, 0.0, 0.0);
+  geometry.vertices.push(V3::new(x, 0.0, 0.0));
+  geometry.vertices.push(V3::new(x/2.0, 0.0, 0.0));
+  
+  geometry.weld(epsilon);
+  
+  assert_vertices_eq(&geometry, &[V3::new(x, 0.0, 0.0),
+    V3::new(x/2.0, 0.0, 0.0)]);
+}
+
+#[rstest]
+fn weld_drops_degenerate_triangles() {
+  let mut geometry = Geometry {
+    vertices: vec![
+      V3::new(0.0, 0.0, 0.0),
+      V3::new(1.0, 0.0, 0.0),
+      V3::new(1.0, 0.0, 0.0),
+      V3::new(0.0, 1.0, 0.0),
+    ],
+    triangles: vec![[0, 1, 3], [0, 1, 2], [2, 3, 0]],
+    ..Default::default()
+  };
+  geometry.selection = vec![0, 1];
+  
+  geometry.weld(1e-6);
+  
+  assert_eq!(geometry.vertices.len(), 3);
+  assert_eq!(geometry.triangles, [[0, 1, 2], [1, 2, 0]]);
+  assert!(geometry.selection.is_empty());
+}
+
+//////////////////////////
+// Tests for primitives //
+//////////////////////////
+
+/// Volume enclosed by a closed geometry. Positive if its triangles face
+/// outward
+fn volume(geometry: &Geometry) -> f64 {
+  geometry.triangles.iter().map(|[a, b, c]| {
+    let [a, b, c] = [a, b, c].map(|i| geometry.vertices[*i as usize]);
+    a.dot(&b.cross(&c))/6.0
+  }).sum()
+}
+
+/// Checks that every edge is shared by exactly two triangles that use it in
+/// opposite directions, so there are no holes and winding is consistent
+fn assert_closed(geometry: &Geometry) {
+  let mut edges = std::collections::HashMap::new();
+  
+  for [a, b, c] in &geometry.triangles {
+    assert!(a != b && b != c && c != a, "degenerate triangle");
+    
+    for edge in [(*a, *b), (*b, *c), (*c, *a)] {
+      assert!(edge.0 < geometry.vertices.len() as u32);
+      *edges.entry(edge).or_insert(0) += 1;
+    }
+  }
+  
+  for (&(a, b), &count) in &edges {
+    assert_eq!(count, 1, "edge {} -> {} is used more than once", a, b);
+    assert_eq!(edges.get(&(b, a)), Some(&1), "edge {} -> {} is open", a, b);
+  }
+}
+
+/// Area of a regular polygon with a circumradius of 1
+fn polygon_area(sides: u32) -> f64 {
+  sides as f64/2.0*(2.0*PI/sides as f64).sin()
+}
+
+#[rstest]
+fn cube_is_closed() {
+  assert_closed(&Geometry::cube());
+  assert!((volume(&Geometry::cube()) - 8.0).abs() < 1e-9);
+}
+
+#[rstest]
+#[case(1, 1)]
+#[case(3, 2)]
+fn grid(#[case] x_segments: u32, #[case] y_segments: u32) {
+  let geometry = Geometry::grid(x_segments, y_segments);
+  
+  assert_eq!(geometry.vertices.len() as u32,
+    (x_segments + 1)*(y_segments + 1));
+  assert_eq!(geometry.triangles.len() as u32, 2*x_segments*y_segments);
+  
+  let mut area = 0.0;
+  for [a, b, c] in &geometry.triangles {
+    let [a, b, c] = [a, b, c].map(|i| geometry.vertices[*i as usize]);
+    let normal = (b - a).cross(&(c - a));
+    assert!(normal.x == 0.0 && normal.y == 0.0 && normal.z > 0.0);
+    area += normal.z/2.0;
+  }
+  assert!((area - 4.0).abs() < 1e-9);
+}
+
+#[rstest]
+fn plane() {
+  let geometry = Geometry::plane();
+  
+  assert_eq!(geometry.vertices.len(), 4);
+  assert_eq!(geometry.triangles.len(), 2);
+}
+
+#[rstest]
+fn uv_sphere() {
+  let geometry = Geometry::uv_sphere(8, 4);
+  
+  assert_closed(&geometry);
+  // Two poles and three rings
+  assert_eq!(geometry.vertices.len(), 2 + 3*8);
+  assert_eq!(geometry.triangles.len(), 2*8 + 2*2*8);
+  for vertex in &geometry.vertices {
+    assert!((vertex.norm() - 1.0).abs() < 1e-9);
+  }
+  
+  let fine = Geometry::uv_sphere(64, 32);
+  assert_closed(&fine);
+  assert!((volume(&fine)/(4.0/3.0*PI) - 1.0).abs() < 0.01);
+}
+
+#[rstest]
+#[case(0, 12, 20)]
+#[case(1, 42, 80)]
+#[case(3, 642, 1280)]
+fn icosphere(#[case] subdivisions: u32, #[case] vertices: usize,
+#[case] triangles: usize) {
+  let geometry = Geometry::icosphere(subdivisions);
+  
+  assert_closed(&geometry);
+  assert_eq!(geometry.vertices.len(), vertices);
+  assert_eq!(geometry.triangles.len(), triangles);
+  for vertex in &geometry.vertices {
+    assert!((vertex.norm() - 1.0).abs() < 1e-9);
+  }
+  assert!(volume(&geometry) > 0.0);
+}
+
+#[rstest]
+fn icosphere_approaches_sphere() {
+  let geometry = Geometry::icosphere(4);
+  
+  assert!((volume(&geometry)/(4.0/3.0*PI) - 1.0).abs() < 0.01);
+}
+
+#[rstest]
+#[case(3)]
+#[case(12)]
+fn cylinder(#[case] segments: u32) {
+  let geometry = Geometry::cylinder(segments);
+  
+  assert_closed(&geometry);
+  assert!((volume(&geometry) - 2.0*polygon_area(segments)).abs() < 1e-9);
+}
+
+#[rstest]
+#[case(3)]
+#[case(12)]
+fn cone(#[case] segments: u32) {
+  let geometry = Geometry::cone(segments);
+  
+  assert_closed(&geometry);
+  assert!((volume(&geometry) - 2.0*polygon_area(segments)/3.0).abs() < 1e-9);
+}
+
+#[rstest]
+fn segments_have_a_minimum() {
+  assert_eq!(Geometry::cylinder(0).vertices,
+    Geometry::cylinder(3).vertices);
+}
+
+#[rstest]
+fn torus() {
+  let geometry = Geometry::torus(8, 6, 0.25);
+  
+  assert_closed(&geometry);
+  assert_eq!(geometry.vertices.len(), 8*6);
+  
+  let fine = Geometry::torus(96, 48, 0.25);
+  let expected = 2.0*PI*PI*0.25*0.25;
+  assert!((volume(&fine)/expected - 1.0).abs() < 0.01);
+}
+
+#[rstest]
+#[case(2.0)]
+#[case(0.0)]
+fn capsule(#[case] length: f64) {
+  let geometry = Geometry::capsule(64, 16, length);
+  
+  assert_closed(&geometry);
+  
+  let expected = 4.0/3.0*PI + PI*length;
+  assert!((volume(&geometry)/expected - 1.0).abs() < 0.01);
+  
+  let top = geometry.vertices.iter().map(|vertex| vertex.z)
+    .fold(f64::MIN, f64::max);
+  assert_eq!(top, 1.0 + length/2.0);
+}
+
+#[rstest]
+fn wedge() {
+  let geometry = Geometry::wedge();
+  
+  assert_closed(&geometry);
+  assert!((volume(&geometry) - 4.0).abs() < 1e-9);
+}
+
+#[rstest]
+#[case(1, 0.5)]
+#[case(16, 0.75)]
+fn arch(#[case] segments: u32, #[case] inner_radius: f64) {
+  let geometry = Geometry::arch(segments, inner_radius);
+  
+  assert_closed(&geometry);
+  
+  // The curve is made of segments triangles from the center, each spanning
+  // PI/segments radians
+  let half_ring = segments as f64/2.0*(PI/segments as f64).sin()*
+    (1.0 - inner_radius*inner_radius);
+  assert!((volume(&geometry) - 2.0*half_ring).abs() < 1e-9);
+  
+  for vertex in &geometry.vertices {
+    assert!(vertex.z > -1e-9);
+  }
+}
@@ -0,0 +1,26 @@
+use rstest::rstest;
+use emg::GLTF;
+
+/////////////////////////
+// Tests for materials //
+/////////////////////////
+
+#[rstest]
+fn material_rgba() {
+  let mut gltf = GLTF::new("");
+  gltf.new_material("Orange").rgba(1.0, 0.5, 0.25, 0.75);
+  
+  let material = serde_json::to_value(&gltf.materials[0]).unwrap();
+  assert_eq!(material["pbrMetallicRoughness"]["baseColorFactor"],
+    serde_json::json!([1.0, 0.5, 0.25, 0.75]));
+}
+
+#[rstest]
+fn material_rgb() {
+  let mut gltf = GLTF::new("");
+  gltf.new_material("Orange").rgb(1.0, 0.5, 0.25);
+  
+  let material = serde_json::to_value(&gltf.materials[0]).unwrap();
+  assert_eq!(material["pbrMetallicRoughness"]["baseColorFactor"],
+    serde_json::json!([1.0, 0.5, 0.25, 1.0]));
+}
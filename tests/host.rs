@@ -0,0 +1,200 @@
+#![cfg(feature = "host")]
+
+use rstest::{rstest, fixture};
+use emg::ErrorCode;
+use emg::host::{CacheOptions, EMGModule, Format, HostError, Limits};
+
+/// Tests do not touch the user's cache
+#[fixture]
+fn cache() -> CacheOptions {
+  CacheOptions { no_cache: true, ..Default::default() }
+}
+
+#[fixture]
+fn blocks(cache: CacheOptions) -> EMGModule {
+  EMGModule::from_file("examples/blocks.wasm".as_ref(), &cache).unwrap()
+}
+
+#[rstest]
+fn generate_sunny_day(mut blocks: EMGModule) {
+  let glb = blocks.generate("build_the_model", &[String::from("1")], &[],
+    &Limits::default()).unwrap();
+  
+  assert_eq!(glb, std::fs::read("tests/build_the_model.glb").unwrap());
+}
+
+#[rstest]
+fn generate_format_output(mut blocks: EMGModule) {
+  let glb = blocks.generate("build_the_model", &[String::from("1")], &[],
+    &Limits::default()).unwrap();
+  let (gltf, bin) = emg::host::format_output(glb, &Format::GLTF, None)
+    .unwrap();
+  
+  assert_eq!(gltf, std::fs::read("tests/build_the_model.gltf").unwrap());
+  assert_eq!(bin, None);
+}
+
+#[rstest]
+fn generate_generator_error(mut blocks: EMGModule) {
+  let error = blocks.generate("block", &[], &[String::from("name=")],
+    &Limits::default()).unwrap_err();
+  
+  assert_eq!(error.code(), ErrorCode::Generation as i32);
+  assert_eq!(error.generator(), Some("block"));
+  assert!(matches!(&error, HostError::Generation { message: Some(message),
+    .. } if message == "block name cannot be empty"));
+  assert_eq!(error.to_string(), "model generation returned error code: 2 \
+    (Generation): block name cannot be empty");
+}
+
+#[rstest]
+fn generate_parameter_out_of_range(mut blocks: EMGModule) {
+  let error = blocks.generate("build_the_model", &[String::from("11")], &[],
+    &Limits::default()).unwrap_err();
+  
+  assert_eq!(error.code(), ErrorCode::ParameterOutOfRange as i32);
+  assert_eq!(error.generator(), Some("build_the_model"));
+  assert!(matches!(error, HostError::Parameter { index: Some(0), .. }));
+}
+
+#[rstest]
+fn generate_generator_not_found(mut blocks: EMGModule) {
+  let error = blocks.generate("nonexistent", &[], &[], &Limits::default())
+    .unwrap_err();
+  
+  assert_eq!(error.code(), ErrorCode::ModelGeneratorNotFound as i32);
+  assert_eq!(error.generator(), Some("nonexistent"));
+}
+
+#[rstest]
+fn generate_logs(mut blocks: EMGModule) {
+  blocks.generate("block", &[], &[String::from("name=Sign")],
+    &Limits::default()).unwrap();
+  
+  let logs = blocks.take_logs();
+  assert_eq!(logs.len(), 1);
+  assert_eq!(logs[0].level, "Debug");
+  assert_eq!(logs[0].message, "Building block `Sign`");
+  
+  assert!(blocks.take_logs().is_empty());
+}
+
+#[rstest]
+fn generate_diagnostics(mut blocks: EMGModule) {
+  let glb = blocks.generate("build_the_model", &[String::from("1")], &[],
+    &Limits::default()).unwrap();
+  let diagnostics = blocks.diagnostics();
+  
+  assert_eq!(diagnostics.arguments.len(), 1);
+  assert_eq!(diagnostics.arguments[0].i32(), Some(1));
+  assert!(diagnostics.fuel_consumed.is_some_and(|fuel| fuel > 0));
+  assert!(diagnostics.pointer.is_some());
+  assert_eq!(diagnostics.size, Some(glb.len() as i32));
+}
+
+#[rstest]
+fn generator_metadata(blocks: EMGModule) {
+  let block = blocks.generator("block").unwrap();
+  let names: Vec<_> = block.parameters.iter()
+    .map(|parameter| parameter.name.as_deref().unwrap()).collect();
+  
+  assert_eq!(names, ["color", "finish", "open_top", "name"]);
+  assert_eq!(block.parameters[1].variants, ["Matte", "Glossy", "Metal"]);
+  assert!(blocks.generator("nonexistent").is_none());
+}
+
+#[rstest]
+fn from_file_missing(cache: CacheOptions) {
+  let error = EMGModule::from_file("tests/nonexistent.wasm".as_ref(), &cache)
+    .err().unwrap();
+  
+  assert_eq!(error.code(), ErrorCode::IO as i32);
+  assert!(matches!(&error, HostError::IO { path: Some(path), .. }
+    if path.ends_with("nonexistent.wasm")));
+  assert!(std::error::Error::source(&error).is_some());
+}
+
+#[rstest]
+fn from_file_no_memory(cache: CacheOptions) {
+  let error = EMGModule::from_file("tests/no_memory.wasm".as_ref(), &cache)
+    .err().unwrap();
+  
+  assert_eq!(error.code(), ErrorCode::ModuleNotEMG as i32);
+  assert!(error.to_string().ends_with("missing required memory export \
+    `memory`"));
+}
+
+#[rstest]
+fn format_output_wrong_chunk() {
+  // A .glb header followed by a BIN chunk where the JSON chunk should be
+  let mut glb = b"glTF".to_vec();
+  glb.extend(2u32.to_le_bytes());
+  glb.extend(20u32.to_le_bytes());
+  glb.extend(0u32.to_le_bytes());
+  glb.extend(b"BIN\0");
+  
+  let error = emg::host::format_output(glb, &Format::GLTF, None).unwrap_err();
+  
+  assert_eq!(error.code(), ErrorCode::OutputNotGLB as i32);
+  assert!(matches!(error, HostError::Output { chunk_type: Some(chunk_type),
+    .. } if chunk_type == "BIN\0"));
+}
+
+#[rstest]
+fn precompile_sunny_day(cache: CacheOptions) {
+  let wasm = std::fs::read("examples/blocks.wasm").unwrap();
+  let cwasm = emg::host::precompile(&wasm, &cache).unwrap();
+  
+  // Safe because the .cwasm was just compiled from a trusted .wasm file
+  let mut precompiled = unsafe { EMGModule::from_precompiled(&cwasm) }
+    .unwrap();
+  let glb = precompiled.generate("build_the_model", &[String::from("1")], &[],
+    &Limits::default()).unwrap();
+  
+  assert_eq!(glb, std::fs::read("tests/build_the_model.glb").unwrap());
+}
+
+#[rstest]
+fn from_bytes_precompiled(cache: CacheOptions) {
+  let wasm = std::fs::read("examples/blocks.wasm").unwrap();
+  let cwasm = emg::host::precompile(&wasm, &cache).unwrap();
+  
+  // Precompiled modules are not sandboxed, so must be loaded explicitly
+  let error = EMGModule::from_bytes(&cwasm, &cache).err().unwrap();
+  assert_eq!(error.code(), ErrorCode::WebAssemblyCompile as i32);
+}
+
+#[rstest]
+fn from_precompiled_wasm() {
+  let wasm = std::fs::read("examples/blocks.wasm").unwrap();
+  
+  let error = unsafe { EMGModule::from_precompiled(&wasm) }.err().unwrap();
+  assert_eq!(error.code(), ErrorCode::WebAssemblyCompile as i32);
+}
+
+#[rstest]
+fn evict_cache_least_recently_used() {
+  let directory = std::env::temp_dir().join(format!("emg-test-evict-{}",
+    std::process::id()));
+  let _ = std::fs::remove_dir_all(&directory);
+  std::fs::create_dir_all(&directory).unwrap();
+  
+  // Written oldest first, then `a` is used again
+  let start = std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+  for (i, name) in ["a", "b", "c"].iter().enumerate() {
+    let path = directory.join(name);
+    std::fs::write(&path, [0; 10]).unwrap();
+    std::fs::File::options().write(true).open(&path).unwrap()
+      .set_modified(start + std::time::Duration::from_secs(i as u64)).unwrap();
+  }
+  emg::host::touch_cache_entry(&directory.join("a"));
+  
+  emg::host::evict_cache(&directory, 20);
+  
+  let mut remaining: Vec<_> = std::fs::read_dir(&directory).unwrap()
+    .map(|entry| entry.unwrap().file_name()).collect();
+  remaining.sort();
+  assert_eq!(remaining, ["a", "c"]);
+  
+  std::fs::remove_dir_all(&directory).unwrap();
+}